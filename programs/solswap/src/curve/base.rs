@@ -7,12 +7,17 @@ use solana_program::{
 
 use crate::curve::{
     calculator::{CurveCalculator, SwapWithoutFeesResult, TradeDirection},
+    concentrated_liquidity::ConcentratedLiquidityCurve,
     constant_price::ConstantPriceCurve,
     constant_product::ConstantProductCurve,
     fees::CurveFees,
+    liquidity_bootstrapping::LiquidityBootstrappingCurve,
     offset::OffsetCurve,
+    oracle_pegged::OraclePeggedCurve,
     stable::StableCurve,
+    weighted::WeightedCurve,
 };
+use anchor_lang::prelude::{borsh, AnchorDeserialize, AnchorSerialize};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use std::convert::{TryFrom, TryInto};
 use std::fmt::Debug;
@@ -33,10 +38,18 @@ pub enum CurveType {
     Stable,
     /// Offset curve, like Uniswap, but the token B side has a faked offset
     Offset,
+    /// Balancer-style weighted curve, like Uniswap, but with unequal token weights
+    Weighted,
+    /// Weighted curve whose weights shift linearly between two timestamps
+    LiquidityBootstrapping,
+    /// Concentrated liquidity curve, like Uniswap, but only within a price range
+    ConcentratedLiquidity,
+    /// Stable curve centered on the price reported by an oracle
+    OraclePegged,
 }
 
 /// Encodes all results of swapping from a source token to a destination token
-#[derive(Debug, PartialEq)]
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, PartialEq)]
 pub struct SwapResult {
     /// New amount of source token
     pub new_swap_source_amount: u128,
@@ -104,6 +117,42 @@ impl SwapCurve {
         })
     }
 
+    /// Calculate how much source token, including fees, must be provided to
+    /// receive exactly the given amount of destination token.
+    pub fn swap_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &CurveFees,
+    ) -> Option<SwapResult> {
+        let SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        } = self.calculator.swap_without_fees_exact_out(
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )?;
+
+        // gross up the source amount so that the fees are still debited from it
+        let source_amount_swapped = fees.pre_trading_fee_amount(source_amount_swapped)?;
+        let trade_fee = fees.trading_fee(source_amount_swapped)?;
+        let owner_fee = fees.owner_trading_fee(source_amount_swapped)?;
+
+        Some(SwapResult {
+            new_swap_source_amount: swap_source_amount.checked_add(source_amount_swapped)?,
+            new_swap_destination_amount: swap_destination_amount
+                .checked_sub(destination_amount_swapped)?,
+            source_amount_swapped,
+            destination_amount_swapped,
+            trade_fee,
+            owner_fee,
+        })
+    }
+
     /// Get the amount of pool tokens for the deposited amount of token A or B
     pub fn deposit_single_token_type(
         &self,
@@ -223,6 +272,16 @@ impl Pack for SwapCurve {
                 }
                 CurveType::Stable => Box::new(StableCurve::unpack_from_slice(calculator)?),
                 CurveType::Offset => Box::new(OffsetCurve::unpack_from_slice(calculator)?),
+                CurveType::Weighted => Box::new(WeightedCurve::unpack_from_slice(calculator)?),
+                CurveType::LiquidityBootstrapping => {
+                    Box::new(LiquidityBootstrappingCurve::unpack_from_slice(calculator)?)
+                }
+                CurveType::ConcentratedLiquidity => {
+                    Box::new(ConcentratedLiquidityCurve::unpack_from_slice(calculator)?)
+                }
+                CurveType::OraclePegged => {
+                    Box::new(OraclePeggedCurve::unpack_from_slice(calculator)?)
+                }
             },
         })
     }
@@ -253,6 +312,10 @@ impl TryFrom<u8> for CurveType {
             1 => Ok(CurveType::ConstantPrice),
             2 => Ok(CurveType::Stable),
             3 => Ok(CurveType::Offset),
+            4 => Ok(CurveType::Weighted),
+            5 => Ok(CurveType::LiquidityBootstrapping),
+            6 => Ok(CurveType::ConcentratedLiquidity),
+            7 => Ok(CurveType::OraclePegged),
             _ => Err(ProgramError::InvalidAccountData),
         }
     }
@@ -373,6 +436,48 @@ mod tests {
         assert_eq!(result.owner_fee, 1);
     }
 
+    #[test]
+    fn constant_product_swap_exact_out_fees() {
+        let swap_source_amount: u128 = 1_000;
+        let swap_destination_amount: u128 = 50_000;
+        let fees = CurveFees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 100,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 100,
+            ..CurveFees::default()
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let result = swap_curve
+            .swap_exact_out(
+                4_545,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, 4_545);
+        assert_eq!(result.source_amount_swapped, 102);
+        assert_eq!(result.trade_fee, 1);
+        assert_eq!(result.owner_fee, 1);
+
+        // swapping the grossed up amount back yields at least the exact amount
+        let forward = swap_curve
+            .swap(
+                result.source_amount_swapped,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        assert!(forward.destination_amount_swapped >= 4_545);
+    }
+
     #[test]
     fn constant_product_no_fee() {
         let swap_source_amount: u128 = 1_000;
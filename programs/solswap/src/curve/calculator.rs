@@ -1,6 +1,11 @@
 //! Swap calculations
 
-use {crate::SwapError, spl_math::precise_number::PreciseNumber, std::fmt::Debug};
+use {
+    crate::SwapError,
+    anchor_lang::prelude::{borsh, AnchorDeserialize, AnchorSerialize},
+    spl_math::precise_number::PreciseNumber,
+    std::fmt::Debug,
+};
 
 #[cfg(feature = "fuzz")]
 use arbitrary::Arbitrary;
@@ -28,7 +33,7 @@ pub fn map_zero_to_none(x: u128) -> Option<u128> {
 /// token differently (by adding offsets or weights)
 #[cfg_attr(feature = "fuzz", derive(Arbitrary))]
 #[repr(C)]
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
 pub enum TradeDirection {
     /// Input token A, output token B
     AtoB,
@@ -95,6 +100,17 @@ pub trait CurveCalculator: Debug + DynPack {
         trade_direction: TradeDirection,
     ) -> Option<SwapWithoutFeesResult>;
 
+    /// Calculate how much source token is required to receive exactly the
+    /// given amount of destination token, the inverse of `swap_without_fees`.
+    /// Amounts are rounded in favor of the pool.
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult>;
+
     /// Get the supply for a new pool
     /// The default implementation is a Balancer-style fixed initial supply
     fn new_pool_supply(&self) -> u128 {
@@ -173,6 +189,19 @@ pub trait CurveCalculator: Debug + DynPack {
         true
     }
 
+    /// Calculates the marginal price of token B in token A, ie. the amount of
+    /// token A paid for an infinitesimal amount of token B, given the
+    /// liquidity parameters.
+    ///
+    /// This is used for time-weighted average prices, price impact limits and
+    /// quoting, and is undefined if the pool is empty on a side the price
+    /// depends on.
+    fn spot_price(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber>;
+
     /// Calculates the total normalized value of the curve given the liquidity
     /// parameters.
     ///
@@ -281,11 +310,7 @@ pub mod test {
             1,
             pool_tokens_total_separate * epsilon_in_basis_points / 10000,
         );
-        let difference = if pool_tokens_from_one_side >= pool_tokens_total_separate {
-            pool_tokens_from_one_side - pool_tokens_total_separate
-        } else {
-            pool_tokens_total_separate - pool_tokens_from_one_side
-        };
+        let difference = pool_tokens_from_one_side.abs_diff(pool_tokens_total_separate);
         assert!(
             difference <= epsilon,
             "difference expected to be less than {}, actually {}",
@@ -365,11 +390,7 @@ pub mod test {
 
         // slippage due to rounding or truncation errors
         let epsilon = std::cmp::max(1, pool_token_amount * epsilon_in_basis_points / 10000);
-        let difference = if pool_token_amount >= pool_token_amount_from_single_side_withdraw {
-            pool_token_amount - pool_token_amount_from_single_side_withdraw
-        } else {
-            pool_token_amount_from_single_side_withdraw - pool_token_amount
-        };
+        let difference = pool_token_amount.abs_diff(pool_token_amount_from_single_side_withdraw);
         assert!(
             difference <= epsilon,
             "difference expected to be less than {}, actually {}",
@@ -436,6 +457,51 @@ pub mod test {
         assert!(difference <= epsilon);
     }
 
+    /// Test function checking that an exact-out swap provides at least the
+    /// requested destination amount and never reduces the overall value of
+    /// the pool.
+    pub fn check_curve_value_from_swap_exact_out(
+        curve: &dyn CurveCalculator,
+        destination_token_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) {
+        let results = curve
+            .swap_without_fees_exact_out(
+                destination_token_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+            )
+            .unwrap();
+        assert_eq!(results.destination_amount_swapped, destination_token_amount);
+
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_source_amount, swap_destination_amount),
+            TradeDirection::BtoA => (swap_destination_amount, swap_source_amount),
+        };
+        let previous_value = curve
+            .normalized_value(swap_token_a_amount, swap_token_b_amount)
+            .unwrap();
+
+        let new_swap_source_amount = swap_source_amount
+            .checked_add(results.source_amount_swapped)
+            .unwrap();
+        let new_swap_destination_amount = swap_destination_amount
+            .checked_sub(results.destination_amount_swapped)
+            .unwrap();
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (new_swap_source_amount, new_swap_destination_amount),
+            TradeDirection::BtoA => (new_swap_destination_amount, new_swap_source_amount),
+        };
+
+        let new_value = curve
+            .normalized_value(swap_token_a_amount, swap_token_b_amount)
+            .unwrap();
+        assert!(new_value.greater_than_or_equal(&previous_value));
+    }
+
     /// Test function checking that a deposit never reduces the value of pool
     /// tokens.
     ///
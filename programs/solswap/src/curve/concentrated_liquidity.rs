@@ -0,0 +1,607 @@
+//! Uniswap v3-style concentrated liquidity curve, a constant product curve on
+//! virtual reserves which only provides liquidity within a price range
+
+use {
+    crate::{
+        curve::{
+            calculator::{
+                CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+                TradingTokenResult,
+            },
+            constant_product::{pool_tokens_to_trading_tokens, spot_price, swap, swap_exact_out},
+        },
+        SwapError,
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::precise_number::PreciseNumber,
+};
+
+/// Denominator of the range prices, which are expressed as the amount of
+/// token A per token B, multiplied by this value
+pub const PRICE_DENOMINATOR: u64 = 1_000_000_000;
+
+/// ConcentratedLiquidityCurve struct implementing CurveCalculator
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConcentratedLiquidityCurve {
+    /// Lowest price of token B in token A at which the pool provides liquidity
+    pub price_lower: u64,
+    /// Highest price of token B in token A at which the pool provides liquidity
+    pub price_upper: u64,
+}
+
+impl ConcentratedLiquidityCurve {
+    /// Square roots of the lower and upper prices
+    fn sqrt_prices(&self) -> Option<(PreciseNumber, PreciseNumber)> {
+        let denominator = PreciseNumber::new(PRICE_DENOMINATOR as u128)?;
+        let sqrt_price_lower = PreciseNumber::new(self.price_lower as u128)?
+            .checked_div(&denominator)?
+            .sqrt()?;
+        let sqrt_price_upper = PreciseNumber::new(self.price_upper as u128)?
+            .checked_div(&denominator)?
+            .sqrt()?;
+        Some((sqrt_price_lower, sqrt_price_upper))
+    }
+
+    /// Liquidity of the pool given its real reserves, solving
+    /// `(token_a + L * sqrt_lower) * (token_b + L / sqrt_upper) = L ^ 2`
+    /// for `L`, which gives:
+    ///
+    /// `L = (b + sqrt(b ^ 2 + 4 * a * token_a * token_b)) / (2 * a)`
+    ///
+    /// with `a = 1 - sqrt_lower / sqrt_upper` and
+    /// `b = token_a / sqrt_upper + token_b * sqrt_lower`
+    pub fn liquidity(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let (sqrt_price_lower, sqrt_price_upper) = self.sqrt_prices()?;
+        let token_a_amount = PreciseNumber::new(swap_token_a_amount)?;
+        let token_b_amount = PreciseNumber::new(swap_token_b_amount)?;
+        let two = PreciseNumber::new(2)?;
+
+        let a = PreciseNumber::new(1)?
+            .checked_sub(&sqrt_price_lower.checked_div(&sqrt_price_upper)?)?;
+        let b = token_a_amount
+            .checked_div(&sqrt_price_upper)?
+            .checked_add(&token_b_amount.checked_mul(&sqrt_price_lower)?)?;
+        let discriminant = b.checked_mul(&b)?.checked_add(
+            &two.checked_mul(&two)?
+                .checked_mul(&a)?
+                .checked_mul(&token_a_amount)?
+                .checked_mul(&token_b_amount)?,
+        )?;
+        b.checked_add(&discriminant.sqrt()?)?
+            .checked_div(&two.checked_mul(&a)?)
+    }
+
+    /// Virtual amounts added to the real token A and token B reserves, so that
+    /// the constant product curve on the virtual reserves runs out of a token
+    /// exactly at the range edges
+    fn virtual_offsets(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<(PreciseNumber, PreciseNumber)> {
+        let (sqrt_price_lower, sqrt_price_upper) = self.sqrt_prices()?;
+        let liquidity = self.liquidity(swap_token_a_amount, swap_token_b_amount)?;
+        let token_a_offset = liquidity.checked_mul(&sqrt_price_lower)?;
+        let token_b_offset = liquidity.checked_div(&sqrt_price_upper)?;
+        Some((token_a_offset, token_b_offset))
+    }
+
+    /// Virtual source and destination reserves for a trade.  The source
+    /// offset is rounded up and the destination offset down, so that rounding
+    /// always favors the pool.
+    fn virtual_reserves(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<(u128, u128)> {
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_source_amount, swap_destination_amount),
+            TradeDirection::BtoA => (swap_destination_amount, swap_source_amount),
+        };
+        let (token_a_offset, token_b_offset) =
+            self.virtual_offsets(swap_token_a_amount, swap_token_b_amount)?;
+        let (source_offset, destination_offset) = match trade_direction {
+            TradeDirection::AtoB => (token_a_offset, token_b_offset),
+            TradeDirection::BtoA => (token_b_offset, token_a_offset),
+        };
+        Some((
+            swap_source_amount.checked_add(source_offset.ceiling()?.to_imprecise()?)?,
+            swap_destination_amount.checked_add(destination_offset.floor()?.to_imprecise()?)?,
+        ))
+    }
+
+    /// Pool tokens corresponding to the change in liquidity from moving the
+    /// real reserves of one side from `swap_source_amount` to
+    /// `new_swap_source_amount`
+    #[allow(clippy::too_many_arguments)]
+    fn liquidity_change_to_pool_tokens(
+        &self,
+        swap_source_amount: u128,
+        new_swap_source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        round_direction: RoundDirection,
+    ) -> Option<u128> {
+        let liquidity = self.liquidity(swap_token_a_amount, swap_token_b_amount)?;
+        let new_liquidity = match trade_direction {
+            TradeDirection::AtoB => self.liquidity(new_swap_source_amount, swap_token_b_amount)?,
+            TradeDirection::BtoA => self.liquidity(swap_token_a_amount, new_swap_source_amount)?,
+        };
+        let liquidity_change = if new_swap_source_amount >= swap_source_amount {
+            new_liquidity.checked_sub(&liquidity)?
+        } else {
+            liquidity.checked_sub(&new_liquidity)?
+        };
+        let pool_tokens = PreciseNumber::new(pool_supply)?
+            .checked_mul(&liquidity_change)?
+            .checked_div(&liquidity)?;
+        match round_direction {
+            RoundDirection::Floor => pool_tokens.floor()?.to_imprecise(),
+            RoundDirection::Ceiling => pool_tokens.ceiling()?.to_imprecise(),
+        }
+    }
+}
+
+impl CurveCalculator for ConcentratedLiquidityCurve {
+    /// Constant product swap on the virtual reserves.  If the trade would move
+    /// the price past the edge of the range, it stops at the edge, providing
+    /// all of the destination token and only taking the source token required
+    /// to get there.
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        if swap_destination_amount == 0 {
+            return None;
+        }
+        let (virtual_source_amount, virtual_destination_amount) =
+            self.virtual_reserves(swap_source_amount, swap_destination_amount, trade_direction)?;
+        let result = swap(
+            source_amount,
+            virtual_source_amount,
+            virtual_destination_amount,
+        )?;
+        if result.destination_amount_swapped < swap_destination_amount {
+            Some(result)
+        } else {
+            swap_exact_out(
+                swap_destination_amount,
+                virtual_source_amount,
+                virtual_destination_amount,
+            )
+        }
+    }
+
+    /// Constant product exact-out swap on the virtual reserves, which cannot
+    /// provide more than the real destination liquidity
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        if destination_amount > swap_destination_amount {
+            return None;
+        }
+        let (virtual_source_amount, virtual_destination_amount) =
+            self.virtual_reserves(swap_source_amount, swap_destination_amount, trade_direction)?;
+        swap_exact_out(
+            destination_amount,
+            virtual_source_amount,
+            virtual_destination_amount,
+        )
+    }
+
+    /// Pool tokens represent a proportional share of the real reserves.  When
+    /// the price is outside of the range, the pool only holds one of the
+    /// tokens, and the amount of the other token is zero.
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        let result = pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            round_direction,
+        )?;
+        // a side with reserves must always give at least one token, otherwise
+        // the pool tokens are worth nothing on that side
+        if (swap_token_a_amount > 0 && result.token_a_amount == 0)
+            || (swap_token_b_amount > 0 && result.token_b_amount == 0)
+        {
+            return None;
+        }
+        Some(result)
+    }
+
+    /// Pool tokens for the increase in liquidity from the deposit
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let swap_source_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => swap_token_b_amount,
+        };
+        self.liquidity_change_to_pool_tokens(
+            swap_source_amount,
+            swap_source_amount.checked_add(source_amount)?,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+            RoundDirection::Floor,
+        )
+    }
+
+    /// Pool tokens for the decrease in liquidity from the withdrawal
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let swap_source_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => swap_token_b_amount,
+        };
+        self.liquidity_change_to_pool_tokens(
+            swap_source_amount,
+            swap_source_amount.checked_sub(source_amount)?,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+            RoundDirection::Ceiling,
+        )
+    }
+
+    /// The range must be non-empty and start above zero
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.price_lower == 0 || self.price_lower >= self.price_upper {
+            Err(SwapError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The pool can start out of range with only one of the tokens, but needs
+    /// some liquidity
+    fn validate_supply(&self, token_a_amount: u64, token_b_amount: u64) -> Result<(), SwapError> {
+        if token_a_amount == 0 && token_b_amount == 0 {
+            return Err(SwapError::EmptySupply);
+        }
+        Ok(())
+    }
+
+    /// The spot price of the concentrated liquidity curve is the constant
+    /// product one on the virtual reserves, which stays within the price range
+    fn spot_price(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let (token_a_offset, token_b_offset) =
+            self.virtual_offsets(swap_token_a_amount, swap_token_b_amount)?;
+        spot_price(
+            swap_token_a_amount.checked_add(token_a_offset.floor()?.to_imprecise()?)?,
+            swap_token_b_amount.checked_add(token_b_offset.floor()?.to_imprecise()?)?,
+        )
+    }
+
+    /// The normalized value of the concentrated liquidity curve is its
+    /// liquidity, the square root of the invariant on the virtual reserves.
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        self.liquidity(swap_token_a_amount, swap_token_b_amount)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for ConcentratedLiquidityCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for ConcentratedLiquidityCurve {}
+impl Pack for ConcentratedLiquidityCurve {
+    const LEN: usize = 16;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<ConcentratedLiquidityCurve, ProgramError> {
+        let input = array_ref![input, 0, 16];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (price_lower, price_upper) = array_refs![input, 8, 8];
+        Ok(Self {
+            price_lower: u64::from_le_bytes(*price_lower),
+            price_upper: u64::from_le_bytes(*price_upper),
+        })
+    }
+}
+
+impl DynPack for ConcentratedLiquidityCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 16];
+        let (price_lower, price_upper) = mut_array_refs![output, 8, 8];
+        *price_lower = self.price_lower.to_le_bytes();
+        *price_upper = self.price_upper.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::calculator::test::{
+        check_pool_value_from_deposit, check_pool_value_from_withdraw,
+    };
+    use proptest::prelude::*;
+
+    /// Range from 0.25 to 4 token A per token B
+    fn curve() -> ConcentratedLiquidityCurve {
+        ConcentratedLiquidityCurve {
+            price_lower: PRICE_DENOMINATOR / 4,
+            price_upper: PRICE_DENOMINATOR * 4,
+        }
+    }
+
+    #[test]
+    fn pack_curve() {
+        let curve = curve();
+
+        let mut packed = [0u8; ConcentratedLiquidityCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = ConcentratedLiquidityCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    #[test]
+    fn validate_range() {
+        assert!(curve().validate().is_ok());
+        assert!(ConcentratedLiquidityCurve {
+            price_lower: 0,
+            price_upper: PRICE_DENOMINATOR,
+        }
+        .validate()
+        .is_err());
+        assert!(ConcentratedLiquidityCurve {
+            price_lower: PRICE_DENOMINATOR,
+            price_upper: PRICE_DENOMINATOR,
+        }
+        .validate()
+        .is_err());
+        assert!(curve().validate_supply(0, 0).is_err());
+        assert!(curve().validate_supply(100, 0).is_ok());
+        assert!(curve().validate_supply(0, 100).is_ok());
+    }
+
+    #[test]
+    fn liquidity_at_center() {
+        // at a price of 1, with sqrt prices of 1/2 and 2, the virtual
+        // reserves are twice the real reserves, so L = 2 * 1_000
+        let liquidity = curve().liquidity(1_000, 1_000).unwrap();
+        assert_eq!(liquidity.to_imprecise().unwrap(), 2_000);
+        let (token_a_offset, token_b_offset) = curve().virtual_offsets(1_000, 1_000).unwrap();
+        assert_eq!(token_a_offset.to_imprecise().unwrap(), 1_000);
+        assert_eq!(token_b_offset.to_imprecise().unwrap(), 1_000);
+    }
+
+    #[test]
+    fn spot_price_at_range_edges() {
+        let scale = PreciseNumber::new(1_000).unwrap();
+        // a pool holding only token A has sold all of its token B, which is
+        // then priced at the top of the range
+        let price = curve()
+            .spot_price(1_000_000, 0)
+            .unwrap()
+            .checked_mul(&scale)
+            .unwrap()
+            .to_imprecise()
+            .unwrap();
+        assert!((3_990..=4_010).contains(&price));
+        let price = curve()
+            .spot_price(0, 1_000_000)
+            .unwrap()
+            .checked_mul(&scale)
+            .unwrap()
+            .to_imprecise()
+            .unwrap();
+        assert!((240..=260).contains(&price));
+    }
+
+    #[test]
+    fn swap_within_range() {
+        let result = curve()
+            .swap_without_fees(100, 1_000, 1_000, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 100);
+        // more output than the 90 from a constant product curve on the real
+        // reserves, since liquidity is concentrated
+        assert!(result.destination_amount_swapped > 90);
+        assert!(result.destination_amount_swapped < 100);
+    }
+
+    #[test]
+    fn swap_stops_at_range_edge() {
+        let swap_source_amount: u128 = 1_000;
+        let swap_destination_amount: u128 = 1_000;
+        let result = curve()
+            .swap_without_fees(
+                1_000_000,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, swap_destination_amount);
+        assert!(result.source_amount_swapped < 1_000_000);
+
+        // out of range, the pool has nothing more to give
+        assert!(curve()
+            .swap_without_fees(
+                100,
+                swap_source_amount + result.source_amount_swapped,
+                0,
+                TradeDirection::AtoB,
+            )
+            .is_none());
+        // but can trade back into range
+        let result = curve()
+            .swap_without_fees(
+                100,
+                0,
+                swap_source_amount + result.source_amount_swapped,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 100);
+        assert!(result.destination_amount_swapped > 0);
+    }
+
+    #[test]
+    fn out_of_range_deposit() {
+        let result = curve()
+            .pool_tokens_to_trading_tokens(10, 100, 0, 1_000, RoundDirection::Ceiling)
+            .unwrap();
+        assert_eq!(result.token_a_amount, 0);
+        assert_eq!(result.token_b_amount, 100);
+        let pool_tokens = curve()
+            .deposit_single_token_type(100, 0, 1_000, 100, TradeDirection::BtoA)
+            .unwrap();
+        assert!(pool_tokens == 9 || pool_tokens == 10);
+    }
+
+    #[test]
+    fn one_side_rounding_to_zero() {
+        // the token A side rounds down to nothing while token B does not
+        assert!(curve()
+            .pool_tokens_to_trading_tokens(1, 1_000, 10, 1_000_000, RoundDirection::Floor)
+            .is_none());
+        assert!(curve()
+            .pool_tokens_to_trading_tokens(1, 1_000, 10, 1_000_000, RoundDirection::Ceiling)
+            .is_none());
+        let result = curve()
+            .pool_tokens_to_trading_tokens(100, 1_000, 10, 1_000_000, RoundDirection::Floor)
+            .unwrap();
+        assert_eq!(result.token_a_amount, 1);
+        assert_eq!(result.token_b_amount, 100_000);
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            source_token_amount in 1..u32::MAX as u64,
+            swap_source_amount in 1..u32::MAX as u64,
+            swap_destination_amount in 1..u32::MAX as u64,
+        ) {
+            let curve = curve();
+            let source_token_amount = source_token_amount as u128;
+            let swap_source_amount = swap_source_amount as u128;
+            let swap_destination_amount = swap_destination_amount as u128;
+            let results = curve.swap_without_fees(
+                source_token_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            );
+            // tiny swaps can give nothing back, which is rejected
+            if let Some(results) = results {
+                let previous_value = curve
+                    .normalized_value(swap_source_amount, swap_destination_amount)
+                    .unwrap();
+                let new_value = curve
+                    .normalized_value(
+                        swap_source_amount + results.source_amount_swapped,
+                        swap_destination_amount - results.destination_amount_swapped,
+                    )
+                    .unwrap();
+                prop_assert!(results.source_amount_swapped <= source_token_amount);
+                prop_assert!(new_value.greater_than_or_equal(&previous_value));
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_deposit(
+            pool_token_amount in 1..u32::MAX as u64,
+            pool_token_supply in 1..u32::MAX as u64,
+            swap_token_a_amount in 1..u32::MAX as u64,
+            swap_token_b_amount in 1..u32::MAX as u64,
+        ) {
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            check_pool_value_from_deposit(
+                &curve(),
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_withdraw(
+            pool_token_amount in 1..u32::MAX as u64,
+            pool_token_supply in 1..u32::MAX as u64,
+            swap_token_a_amount in 1..u32::MAX as u64,
+            swap_token_b_amount in 1..u32::MAX as u64,
+        ) {
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            prop_assume!(pool_token_amount < pool_token_supply);
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            check_pool_value_from_withdraw(
+                &curve(),
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+}
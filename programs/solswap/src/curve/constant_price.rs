@@ -98,6 +98,31 @@ impl CurveCalculator for ConstantPriceCurve {
         })
     }
 
+    /// Constant price exact-out swap, rounding the source amount up whenever
+    /// the destination amount is not a multiple of the price
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        _swap_source_amount: u128,
+        _swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let token_b_price = self.token_b_price as u128;
+
+        let source_amount_swapped = match trade_direction {
+            TradeDirection::AtoB => destination_amount.checked_mul(token_b_price)?,
+            TradeDirection::BtoA => destination_amount
+                .checked_add(token_b_price.checked_sub(1)?)?
+                .checked_div(token_b_price)?,
+        };
+        let source_amount_swapped = map_zero_to_none(source_amount_swapped)?;
+        let destination_amount_swapped = map_zero_to_none(destination_amount)?;
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        })
+    }
+
     /// Get the amount of trading tokens for the given amount of pool tokens,
     /// provided the total trading tokens and supply of pool tokens.
     /// For the constant price curve, the total value of the pool is weighted
@@ -200,6 +225,16 @@ impl CurveCalculator for ConstantPriceCurve {
         Ok(())
     }
 
+    /// The spot price of the constant price curve is the fixed price of token
+    /// B, whatever the token amounts
+    fn spot_price(
+        &self,
+        _swap_token_a_amount: u128,
+        _swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        PreciseNumber::new(self.token_b_price as u128)
+    }
+
     /// The total normalized value of the constant price curve adds the total
     /// value of the token B side to the token A side.
     ///
@@ -216,8 +251,8 @@ impl CurveCalculator for ConstantPriceCurve {
     ) -> Option<PreciseNumber> {
         let swap_token_b_value = swap_token_b_amount.checked_mul(self.token_b_price as u128)?;
         // special logic in case we're close to the limits, avoid overflowing u128
-        let value = if swap_token_b_value.saturating_sub(std::u64::MAX.into())
-            > (std::u128::MAX.saturating_sub(std::u64::MAX.into()))
+        let value = if swap_token_b_value.saturating_sub(u64::MAX.into())
+            > (u128::MAX.saturating_sub(u64::MAX.into()))
         {
             swap_token_b_value
                 .checked_div(2)?
@@ -322,6 +357,18 @@ mod tests {
         assert_eq!(curve, unpacked);
     }
 
+    #[test]
+    fn spot_price_is_fixed() {
+        let token_b_price = 1_251_258;
+        let curve = ConstantPriceCurve { token_b_price };
+        for (swap_token_a_amount, swap_token_b_amount) in [(0, 0), (1_000, 5), (5, 1_000)] {
+            let price = curve
+                .spot_price(swap_token_a_amount, swap_token_b_amount)
+                .unwrap();
+            assert_eq!(price.to_imprecise().unwrap(), token_b_price as u128);
+        }
+    }
+
     #[test]
     fn swap_calculation_large_price() {
         let token_b_price = 1123513u128;
@@ -385,6 +432,28 @@ mod tests {
         assert_eq!(result.destination_amount_swapped, 1u128);
     }
 
+    #[test]
+    fn swap_exact_out_calculation() {
+        let token_b_price = 3;
+        let curve = ConstantPriceCurve { token_b_price };
+
+        let result = curve
+            .swap_without_fees_exact_out(2, 100, 100, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 6);
+        assert_eq!(result.destination_amount_swapped, 2);
+
+        // 10 token A costs 3.33 token B, rounded up
+        let result = curve
+            .swap_without_fees_exact_out(10, 100, 100, TradeDirection::BtoA)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 4);
+        assert_eq!(result.destination_amount_swapped, 10);
+
+        let bad_result = curve.swap_without_fees_exact_out(0, 100, 100, TradeDirection::BtoA);
+        assert!(bad_result.is_none());
+    }
+
     proptest! {
         #[test]
         fn deposit_token_conversion_a_to_b(
@@ -568,7 +637,6 @@ mod tests {
         ) {
             let curve = ConstantPriceCurve { token_b_price: token_b_price as u64 };
             let pool_token_amount = pool_token_amount as u128;
-            let pool_token_supply = pool_token_supply as u128;
             let swap_token_a_amount = swap_token_a_amount as u128;
             let swap_token_b_amount = swap_token_b_amount as u128;
             let token_b_price = token_b_price as u128;
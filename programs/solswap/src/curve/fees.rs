@@ -62,6 +62,45 @@ pub fn calculate_fee(
     }
 }
 
+/// Helper function for calculating the amount before a fee is deducted, the
+/// inverse of `calculate_fee`: the smallest amount still worth at least
+/// `post_fee_amount` once its fee is deducted
+pub fn pre_fee_amount(
+    post_fee_amount: u128,
+    fee_numerator: u128,
+    fee_denominator: u128,
+) -> Option<u128> {
+    if fee_numerator == 0 || post_fee_amount == 0 {
+        Some(post_fee_amount)
+    } else {
+        // the fee is at least one token
+        Some(std::cmp::max(
+            post_fee_amount.checked_add(1)?,
+            pre_fraction_amount(post_fee_amount, fee_numerator, fee_denominator)?,
+        ))
+    }
+}
+
+/// Smallest amount `x` still worth at least `post_fee_amount` once the fee
+/// fraction, rounded down, is deducted.  Since
+/// `x - floor(x * numerator / denominator) >= post_fee_amount` holds exactly
+/// when `x * (denominator - numerator) > (post_fee_amount - 1) * denominator`,
+/// this is a ceiling division, which fails for a fee of the whole amount.
+fn pre_fraction_amount(
+    post_fee_amount: u128,
+    fee_numerator: u128,
+    fee_denominator: u128,
+) -> Option<u128> {
+    if post_fee_amount == 0 {
+        return Some(0);
+    }
+    post_fee_amount
+        .checked_sub(1)?
+        .checked_mul(fee_denominator)?
+        .checked_div(fee_denominator.checked_sub(fee_numerator)?)?
+        .checked_add(1)
+}
+
 fn validate_fraction(numerator: u64, denominator: u64) -> std::result::Result<(), SwapError> {
     if denominator == 0 && numerator == 0 {
         Ok(())
@@ -100,6 +139,57 @@ impl CurveFees {
         )
     }
 
+    /// Calculate the amount of trading tokens needed so that, after the trade
+    /// and owner trade fees are deducted, at least `post_fee_amount` remains
+    pub fn pre_trading_fee_amount(&self, post_fee_amount: u128) -> Option<u128> {
+        let trade_fee_numerator = u128::try_from(self.trade_fee_numerator).ok()?;
+        let trade_fee_denominator = u128::try_from(self.trade_fee_denominator).ok()?;
+        let owner_trade_fee_numerator = u128::try_from(self.owner_trade_fee_numerator).ok()?;
+        let owner_trade_fee_denominator = u128::try_from(self.owner_trade_fee_denominator).ok()?;
+        if trade_fee_numerator == 0 {
+            return pre_fee_amount(
+                post_fee_amount,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+            );
+        }
+        if owner_trade_fee_numerator == 0 || post_fee_amount == 0 {
+            return pre_fee_amount(post_fee_amount, trade_fee_numerator, trade_fee_denominator);
+        }
+        // each fee is rounded down with a minimum of one token, so the amount
+        // must cover both minimums, either minimum along with the other fee,
+        // and both fees together.  Both fees together are bounded by their
+        // combined fraction rounded down, which can ask for a token more than
+        // strictly needed, but never less.
+        let minimum_fees_amount = post_fee_amount.checked_add(2)?;
+        let minimum_owner_fee_amount = pre_fraction_amount(
+            post_fee_amount.checked_add(1)?,
+            trade_fee_numerator,
+            trade_fee_denominator,
+        )?;
+        let minimum_trade_fee_amount = pre_fraction_amount(
+            post_fee_amount.checked_add(1)?,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+        )?;
+        let combined_fees_amount = pre_fraction_amount(
+            post_fee_amount,
+            trade_fee_numerator
+                .checked_mul(owner_trade_fee_denominator)?
+                .checked_add(owner_trade_fee_numerator.checked_mul(trade_fee_denominator)?)?,
+            trade_fee_denominator.checked_mul(owner_trade_fee_denominator)?,
+        )?;
+        [
+            minimum_fees_amount,
+            minimum_owner_fee_amount,
+            minimum_trade_fee_amount,
+            combined_fees_amount,
+        ]
+        .iter()
+        .copied()
+        .max()
+    }
+
     /// Calculate the host fee based on the owner fee, only used in production
     /// situations where a program is hosted by multiple frontends
     pub fn host_fee(&self, owner_fee: u128) -> Option<u128> {
@@ -184,46 +274,117 @@ impl Pack for CurveFees {
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn pack_fees() {
-//         let trade_fee_numerator = 1;
-//         let trade_fee_denominator = 4;
-//         let owner_trade_fee_numerator = 2;
-//         let owner_trade_fee_denominator = 5;
-//         let owner_withdraw_fee_numerator = 4;
-//         let owner_withdraw_fee_denominator = 10;
-//         let host_fee_numerator = 7;
-//         let host_fee_denominator = 100;
-//         let fees = CurveFees {
-//             trade_fee_numerator,
-//             trade_fee_denominator,
-//             owner_trade_fee_numerator,
-//             owner_trade_fee_denominator,
-//             owner_withdraw_fee_numerator,
-//             owner_withdraw_fee_denominator,
-//             host_fee_numerator,
-//             host_fee_denominator,
-//         };
-
-//         let mut packed = [0u8; CurveFees::LEN];
-//         Pack::pack_into_slice(&fees, &mut packed[..]);
-//         let unpacked = CurveFees::unpack_from_slice(&packed).unwrap();
-//         assert_eq!(fees, unpacked);
-
-//         let mut packed = vec![];
-//         packed.extend_from_slice(&trade_fee_numerator.to_le_bytes());
-//         packed.extend_from_slice(&trade_fee_denominator.to_le_bytes());
-//         packed.extend_from_slice(&owner_trade_fee_numerator.to_le_bytes());
-//         packed.extend_from_slice(&owner_trade_fee_denominator.to_le_bytes());
-//         packed.extend_from_slice(&owner_withdraw_fee_numerator.to_le_bytes());
-//         packed.extend_from_slice(&owner_withdraw_fee_denominator.to_le_bytes());
-//         packed.extend_from_slice(&host_fee_numerator.to_le_bytes());
-//         packed.extend_from_slice(&host_fee_denominator.to_le_bytes());
-//         let unpacked = CurveFees::unpack_from_slice(&packed).unwrap();
-//         assert_eq!(fees, unpacked);
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn pack_fees() {
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 4;
+        let owner_trade_fee_numerator = 2;
+        let owner_trade_fee_denominator = 5;
+        let owner_withdraw_fee_numerator = 4;
+        let owner_withdraw_fee_denominator = 10;
+        let host_fee_numerator = 7;
+        let host_fee_denominator = 100;
+        let fees = CurveFees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let mut packed = [0u8; CurveFees::LEN];
+        Pack::pack_into_slice(&fees, &mut packed[..]);
+        let unpacked = CurveFees::unpack_from_slice(&packed).unwrap();
+        assert_eq!(fees, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&trade_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&trade_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&owner_trade_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&owner_trade_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&owner_withdraw_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&owner_withdraw_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&host_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&host_fee_denominator.to_le_bytes());
+        let unpacked = CurveFees::unpack_from_slice(&packed).unwrap();
+        assert_eq!(fees, unpacked);
+    }
+
+    fn fees(trade_fee_numerator: u64, owner_trade_fee_numerator: u64) -> CurveFees {
+        CurveFees {
+            trade_fee_numerator,
+            trade_fee_denominator: 100,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator: 100,
+            ..CurveFees::default()
+        }
+    }
+
+    fn post_fee_amount(fees: &CurveFees, source_amount: u128) -> Option<u128> {
+        source_amount
+            .checked_sub(fees.trading_fee(source_amount).unwrap())?
+            .checked_sub(fees.owner_trading_fee(source_amount).unwrap())
+    }
+
+    #[test]
+    fn pre_fee_amount_at_the_edges() {
+        // no fee, or a fee on nothing
+        assert_eq!(pre_fee_amount(100, 0, 0), Some(100));
+        assert_eq!(pre_fee_amount(0, 1, 100), Some(0));
+        // a fee of the whole amount can never leave anything
+        assert_eq!(pre_fee_amount(100, 100, 100), None);
+        assert_eq!(pre_fee_amount(100, 101, 100), None);
+        // the minimum fee of one token applies
+        assert_eq!(pre_fee_amount(1, 1, 100), Some(2));
+        assert_eq!(pre_fee_amount(99, 1, 100), Some(100));
+        // 202 pays a fee of 2, while 201 would leave 199
+        assert_eq!(pre_fee_amount(200, 1, 100), Some(202));
+        assert_eq!(calculate_fee(202, 1, 100), Some(2));
+        assert_eq!(calculate_fee(201, 1, 100), Some(2));
+    }
+
+    #[test]
+    fn pre_trading_fee_amount_at_the_edges() {
+        // both minimum fees
+        assert_eq!(fees(1, 1).pre_trading_fee_amount(1), Some(3));
+        // both fees at their minimum of one token on 92 and 91
+        assert_eq!(fees(1, 2).pre_trading_fee_amount(90), Some(92));
+        assert_eq!(post_fee_amount(&fees(1, 2), 91), Some(89));
+        // fees of 2 and 4 on 201, but of 2 and 4 as well on 200
+        assert_eq!(fees(1, 2).pre_trading_fee_amount(195), Some(201));
+        assert_eq!(post_fee_amount(&fees(1, 2), 201), Some(195));
+        assert_eq!(post_fee_amount(&fees(1, 2), 200), Some(194));
+        // fees of the whole amount
+        assert_eq!(fees(50, 50).pre_trading_fee_amount(1), None);
+        assert_eq!(fees(100, 0).pre_trading_fee_amount(1), None);
+        assert_eq!(fees(0, 0).pre_trading_fee_amount(10), Some(10));
+        assert_eq!(fees(1, 1).pre_trading_fee_amount(0), Some(0));
+    }
+
+    proptest! {
+        #[test]
+        fn pre_trading_fee_amount_covers_fees(
+            trade_fee_numerator in 0..50u64,
+            owner_trade_fee_numerator in 0..50u64,
+            post_amount in 0..u32::MAX as u128,
+        ) {
+            let fees = fees(trade_fee_numerator, owner_trade_fee_numerator);
+            let source_amount = fees.pre_trading_fee_amount(post_amount).unwrap();
+            prop_assert!(post_fee_amount(&fees, source_amount).unwrap() >= post_amount);
+            // a single fee is inverted exactly
+            if (trade_fee_numerator == 0 || owner_trade_fee_numerator == 0) && source_amount > 0 {
+                prop_assert!(
+                    post_fee_amount(&fees, source_amount - 1).unwrap_or(0) < post_amount
+                );
+            }
+        }
+    }
+}
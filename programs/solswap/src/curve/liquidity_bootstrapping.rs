@@ -0,0 +1,358 @@
+//! Liquidity bootstrapping pool curve, a weighted curve whose weights shift
+//! linearly over time
+
+use {
+    crate::{
+        curve::{
+            calculator::{
+                CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+                TradingTokenResult,
+            },
+            weighted::WeightedCurve,
+        },
+        SwapError,
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::precise_number::PreciseNumber,
+    std::convert::TryFrom,
+};
+
+/// LiquidityBootstrappingCurve struct implementing CurveCalculator
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LiquidityBootstrappingCurve {
+    /// Weight of token A in basis points at the start of the schedule
+    pub start_token_a_weight: u64,
+    /// Weight of token A in basis points at the end of the schedule
+    pub end_token_a_weight: u64,
+    /// Unix timestamp at which the weight starts to shift
+    pub start_timestamp: i64,
+    /// Unix timestamp at which the weight reaches its end value
+    pub end_timestamp: i64,
+    /// Unix timestamp the curve prices against, not packed since it is only
+    /// known when the instruction executes
+    pub current_timestamp: i64,
+}
+
+impl LiquidityBootstrappingCurve {
+    /// Weight of token A in force at the current timestamp, linearly
+    /// interpolated between the start and end weights
+    pub fn token_a_weight(&self) -> Option<u64> {
+        if self.current_timestamp <= self.start_timestamp {
+            return Some(self.start_token_a_weight);
+        }
+        if self.current_timestamp >= self.end_timestamp {
+            return Some(self.end_token_a_weight);
+        }
+        let elapsed = self.current_timestamp.checked_sub(self.start_timestamp)? as u128;
+        let duration = self.end_timestamp.checked_sub(self.start_timestamp)? as u128;
+        let start_weight = self.start_token_a_weight as u128;
+        let end_weight = self.end_token_a_weight as u128;
+        let weight = if end_weight >= start_weight {
+            start_weight.checked_add(
+                end_weight
+                    .checked_sub(start_weight)?
+                    .checked_mul(elapsed)?
+                    .checked_div(duration)?,
+            )?
+        } else {
+            start_weight.checked_sub(
+                start_weight
+                    .checked_sub(end_weight)?
+                    .checked_mul(elapsed)?
+                    .checked_div(duration)?,
+            )?
+        };
+        u64::try_from(weight).ok()
+    }
+
+    /// Weighted curve using the weight in force at the current timestamp
+    fn weighted_curve(&self) -> Option<WeightedCurve> {
+        Some(WeightedCurve {
+            token_a_weight: self.token_a_weight()?,
+        })
+    }
+}
+
+impl CurveCalculator for LiquidityBootstrappingCurve {
+    /// Weighted swap with the current weights
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        self.weighted_curve()?.swap_without_fees(
+            source_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )
+    }
+
+    /// Weighted exact-out swap with the current weights
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        self.weighted_curve()?.swap_without_fees_exact_out(
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )
+    }
+
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        self.weighted_curve()?.pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            round_direction,
+        )
+    }
+
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        self.weighted_curve()?.deposit_single_token_type(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        self.weighted_curve()?.withdraw_single_token_type_exact_out(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    /// Both ends of the schedule must be valid weights, and the schedule must
+    /// not end before it starts
+    fn validate(&self) -> Result<(), SwapError> {
+        WeightedCurve {
+            token_a_weight: self.start_token_a_weight,
+        }
+        .validate()?;
+        WeightedCurve {
+            token_a_weight: self.end_token_a_weight,
+        }
+        .validate()?;
+        if self.end_timestamp < self.start_timestamp {
+            return Err(SwapError::InvalidCurve);
+        }
+        Ok(())
+    }
+
+    fn spot_price(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        self.weighted_curve()?
+            .spot_price(swap_token_a_amount, swap_token_b_amount)
+    }
+
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        self.weighted_curve()?
+            .normalized_value(swap_token_a_amount, swap_token_b_amount)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for LiquidityBootstrappingCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for LiquidityBootstrappingCurve {}
+impl Pack for LiquidityBootstrappingCurve {
+    const LEN: usize = 32;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<LiquidityBootstrappingCurve, ProgramError> {
+        let input = array_ref![input, 0, 32];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (start_token_a_weight, end_token_a_weight, start_timestamp, end_timestamp) =
+            array_refs![input, 8, 8, 8, 8];
+        let start_timestamp = i64::from_le_bytes(*start_timestamp);
+        Ok(Self {
+            start_token_a_weight: u64::from_le_bytes(*start_token_a_weight),
+            end_token_a_weight: u64::from_le_bytes(*end_token_a_weight),
+            start_timestamp,
+            end_timestamp: i64::from_le_bytes(*end_timestamp),
+            current_timestamp: start_timestamp,
+        })
+    }
+}
+
+impl DynPack for LiquidityBootstrappingCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 32];
+        let (start_token_a_weight, end_token_a_weight, start_timestamp, end_timestamp) =
+            mut_array_refs![output, 8, 8, 8, 8];
+        *start_token_a_weight = self.start_token_a_weight.to_le_bytes();
+        *end_token_a_weight = self.end_token_a_weight.to_le_bytes();
+        *start_timestamp = self.start_timestamp.to_le_bytes();
+        *end_timestamp = self.end_timestamp.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve_at(current_timestamp: i64) -> LiquidityBootstrappingCurve {
+        LiquidityBootstrappingCurve {
+            start_token_a_weight: 9_500,
+            end_token_a_weight: 5_000,
+            start_timestamp: 1_000,
+            end_timestamp: 2_000,
+            current_timestamp,
+        }
+    }
+
+    #[test]
+    fn pack_curve() {
+        let curve = curve_at(1_000);
+
+        let mut packed = [0u8; LiquidityBootstrappingCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = LiquidityBootstrappingCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    #[test]
+    fn weight_interpolation() {
+        assert_eq!(curve_at(0).token_a_weight().unwrap(), 9_500);
+        assert_eq!(curve_at(1_000).token_a_weight().unwrap(), 9_500);
+        assert_eq!(curve_at(1_500).token_a_weight().unwrap(), 7_250);
+        assert_eq!(curve_at(1_900).token_a_weight().unwrap(), 5_450);
+        assert_eq!(curve_at(2_000).token_a_weight().unwrap(), 5_000);
+        assert_eq!(curve_at(i64::MAX).token_a_weight().unwrap(), 5_000);
+
+        let increasing = LiquidityBootstrappingCurve {
+            start_token_a_weight: 5_000,
+            end_token_a_weight: 9_500,
+            ..curve_at(1_500)
+        };
+        assert_eq!(increasing.token_a_weight().unwrap(), 7_250);
+    }
+
+    #[test]
+    fn validate_schedule() {
+        assert!(curve_at(0).validate().is_ok());
+        let backwards = LiquidityBootstrappingCurve {
+            start_timestamp: 2_000,
+            end_timestamp: 1_000,
+            ..curve_at(0)
+        };
+        assert!(backwards.validate().is_err());
+        let bad_weight = LiquidityBootstrappingCurve {
+            start_token_a_weight: 10_000,
+            ..curve_at(0)
+        };
+        assert!(bad_weight.validate().is_err());
+    }
+
+    #[test]
+    fn swap_prices_against_current_weight() {
+        let swap_source_amount: u128 = 1_000_000;
+        let swap_destination_amount: u128 = 1_000_000;
+        let source_amount: u128 = 1_000;
+        let early = curve_at(1_000)
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        let late = curve_at(2_000)
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        // token A gets cheaper as its weight goes down
+        assert!(early.destination_amount_swapped < late.destination_amount_swapped);
+    }
+
+    #[test]
+    fn trade_ratios_capped() {
+        let curve = curve_at(1_500);
+        let swap_token_a_amount: u128 = 1_000_000;
+        let swap_token_b_amount: u128 = 1_000_000;
+        let pool_supply: u128 = 1_000_000;
+        // withdrawing almost all of one side is rejected rather than priced
+        // with an inaccurate power approximation
+        assert!(curve
+            .withdraw_single_token_type_exact_out(
+                999_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            )
+            .is_none());
+        assert!(curve
+            .swap_without_fees_exact_out(
+                999_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .is_none());
+        assert!(curve
+            .withdraw_single_token_type_exact_out(
+                300_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            )
+            .is_some());
+    }
+}
@@ -2,7 +2,13 @@
 
 pub mod base;
 pub mod calculator;
+pub mod concentrated_liquidity;
 pub mod constant_price;
+pub mod constant_product;
 pub mod fees;
+pub mod liquidity_bootstrapping;
 pub mod offset;
+pub mod oracle_pegged;
+pub mod stable;
+pub mod weighted;
 
@@ -8,8 +8,8 @@ use {
                 TradingTokenResult,
             },
             constant_product::{
-                deposit_single_token_type, normalized_value, pool_tokens_to_trading_tokens, swap,
-                withdraw_single_token_type_exact_out,
+                deposit_single_token_type, normalized_value, pool_tokens_to_trading_tokens,
+                spot_price, swap, swap_exact_out, withdraw_single_token_type_exact_out,
             },
         },
         SwapError,
@@ -35,6 +35,7 @@ impl CurveCalculator for OffsetCurve {
     /// This is guaranteed to work for all values such that:
     ///  - 1 <= source_amount <= u64::MAX
     ///  - 1 <= (swap_source_amount * (swap_destination_amount + token_b_offset)) <= u128::MAX
+    ///
     /// If the offset and token B are both close to u64::MAX, there can be
     /// overflow errors with the invariant.
     fn swap_without_fees(
@@ -56,6 +57,34 @@ impl CurveCalculator for OffsetCurve {
         swap(source_amount, swap_source_amount, swap_destination_amount)
     }
 
+    /// The exact-out swap needs the same offset applied to the token B side,
+    /// but can never provide more than the real destination liquidity
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        swap_destination_amount.checked_sub(destination_amount)?;
+        let token_b_offset = self.token_b_offset as u128;
+        let (swap_source_amount, swap_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => (
+                swap_source_amount,
+                swap_destination_amount.checked_add(token_b_offset)?,
+            ),
+            TradeDirection::BtoA => (
+                swap_source_amount.checked_add(token_b_offset)?,
+                swap_destination_amount,
+            ),
+        };
+        swap_exact_out(
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+        )
+    }
+
     /// The conversion for the offset curve needs to take into account the
     /// offset
     fn pool_tokens_to_trading_tokens(
@@ -140,6 +169,20 @@ impl CurveCalculator for OffsetCurve {
         false
     }
 
+    /// The spot price of the offset curve is the constant product one with the
+    /// offset added to the token B side
+    fn spot_price(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let token_b_offset = self.token_b_offset as u128;
+        spot_price(
+            swap_token_a_amount,
+            swap_token_b_amount.checked_add(token_b_offset)?,
+        )
+    }
+
     /// The normalized value of the offset curve simply needs to add the offset to
     /// the token B side before calculating
     fn normalized_value(
@@ -213,6 +256,18 @@ mod tests {
         assert_eq!(curve, unpacked);
     }
 
+    #[test]
+    fn spot_price_with_offset() {
+        let curve = OffsetCurve {
+            token_b_offset: 1_000,
+        };
+        let price = curve.spot_price(4_000, 1_000).unwrap();
+        assert_eq!(price.to_imprecise().unwrap(), 2);
+        // the offset gives a price even without any real token B
+        let price = curve.spot_price(4_000, 0).unwrap();
+        assert_eq!(price.to_imprecise().unwrap(), 4);
+    }
+
     #[test]
     fn swap_no_offset() {
         let swap_source_amount: u128 = 1_000;
@@ -306,6 +361,33 @@ mod tests {
         assert_eq!(result.destination_amount_swapped, 499);
     }
 
+    #[test]
+    fn swap_exact_out_offset() {
+        let swap_source_amount: u128 = 1_000_000;
+        let swap_destination_amount: u128 = 1_000;
+        let token_b_offset = 1_000_000;
+        let curve = OffsetCurve { token_b_offset };
+        let result = curve
+            .swap_without_fees_exact_out(
+                99,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 99);
+        assert_eq!(result.destination_amount_swapped, 99);
+
+        // the offset cannot be withdrawn
+        let bad_result = curve.swap_without_fees_exact_out(
+            swap_destination_amount + 1,
+            swap_source_amount,
+            swap_destination_amount,
+            TradeDirection::AtoB,
+        );
+        assert!(bad_result.is_none());
+    }
+
     prop_compose! {
         pub fn values_sum_within_u64()(total in 1..u64::MAX)
                         (amount in 1..total, total in Just(total))
@@ -473,9 +555,9 @@ mod tests {
                 (swap_source_amount * swap_destination_amount));
             check_curve_value_from_swap(
                 &curve,
-                source_token_amount as u128,
-                swap_source_amount as u128,
-                swap_destination_amount as u128,
+                source_token_amount,
+                swap_source_amount,
+                swap_destination_amount,
                 TradeDirection::AtoB
             );
         }
@@ -501,9 +583,9 @@ mod tests {
             prop_assume!(!(swap_source_amount + token_b_offset).overflowing_mul(swap_destination_amount).1);
             check_curve_value_from_swap(
                 &curve,
-                source_token_amount as u128,
-                swap_source_amount as u128,
-                swap_destination_amount as u128,
+                source_token_amount,
+                swap_source_amount,
+                swap_destination_amount,
                 TradeDirection::BtoA
             );
         }
@@ -0,0 +1,424 @@
+//! Stable curve pegged around an external oracle price
+
+use {
+    crate::{
+        curve::{
+            calculator::{
+                map_zero_to_none, CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult,
+                TradeDirection, TradingTokenResult,
+            },
+            constant_product::pool_tokens_to_trading_tokens,
+            stable::StableCurve,
+        },
+        SwapError,
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::{checked_ceil_div::CheckedCeilDiv, precise_number::PreciseNumber},
+};
+
+/// Denominator of oracle prices, which are expressed as the amount of token A
+/// per token B, multiplied by this value
+pub const ORACLE_PRICE_DENOMINATOR: u64 = 1_000_000_000;
+
+/// Denominator of the spread, which is expressed in basis points
+pub const SPREAD_DENOMINATOR: u64 = 10_000;
+
+/// OraclePeggedCurve struct implementing CurveCalculator
+///
+/// Token B amounts are converted to their value in token A at the oracle
+/// price, and the stable curve is applied to the resulting amounts, so that
+/// the pool quotes close to the oracle price while balanced.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OraclePeggedCurve {
+    /// Amplifier constant of the stable curve around the oracle price
+    pub amp: u64,
+    /// Spread taken from the output of every swap, in basis points
+    pub spread: u64,
+    /// Oracle price of token B in token A, not packed since it is read from
+    /// the price feed when the instruction executes
+    pub oracle_price: u64,
+}
+
+impl OraclePeggedCurve {
+    fn stable_curve(&self) -> StableCurve {
+        StableCurve { amp: self.amp }
+    }
+
+    /// Value in token A of the given amount of token B
+    fn token_b_to_value(&self, token_b_amount: u128, round_direction: RoundDirection) -> Option<u128> {
+        let price = map_zero_to_none(self.oracle_price as u128)?;
+        let value = token_b_amount.checked_mul(price)?;
+        let denominator = ORACLE_PRICE_DENOMINATOR as u128;
+        match round_direction {
+            RoundDirection::Floor => value.checked_div(denominator),
+            RoundDirection::Ceiling => Some(value.checked_ceil_div(denominator)?.0),
+        }
+    }
+
+    /// Amount of token B worth the given value in token A
+    fn value_to_token_b(&self, value: u128, round_direction: RoundDirection) -> Option<u128> {
+        let price = map_zero_to_none(self.oracle_price as u128)?;
+        let value = value.checked_mul(ORACLE_PRICE_DENOMINATOR as u128)?;
+        match round_direction {
+            RoundDirection::Floor => value.checked_div(price),
+            RoundDirection::Ceiling => Some(value.checked_ceil_div(price)?.0),
+        }
+    }
+
+    /// Output of a swap once the spread is taken out
+    fn apply_spread(&self, amount: u128) -> Option<u128> {
+        amount
+            .checked_mul(SPREAD_DENOMINATOR.checked_sub(self.spread)? as u128)?
+            .checked_div(SPREAD_DENOMINATOR as u128)
+    }
+
+    /// Output required before the spread is taken out to end up with `amount`
+    fn remove_spread(&self, amount: u128) -> Option<u128> {
+        let (amount, _) = amount
+            .checked_mul(SPREAD_DENOMINATOR as u128)?
+            .checked_ceil_div(SPREAD_DENOMINATOR.checked_sub(self.spread)? as u128)?;
+        Some(amount)
+    }
+}
+
+impl CurveCalculator for OraclePeggedCurve {
+    /// Stable swap on the token A values, with the spread taken out of the
+    /// output
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let destination_amount_swapped = match trade_direction {
+            TradeDirection::AtoB => {
+                let result = self.stable_curve().swap_without_fees(
+                    source_amount,
+                    swap_source_amount,
+                    self.token_b_to_value(swap_destination_amount, RoundDirection::Floor)?,
+                    trade_direction,
+                )?;
+                self.value_to_token_b(
+                    self.apply_spread(result.destination_amount_swapped)?,
+                    RoundDirection::Floor,
+                )?
+            }
+            TradeDirection::BtoA => {
+                let result = self.stable_curve().swap_without_fees(
+                    self.token_b_to_value(source_amount, RoundDirection::Floor)?,
+                    self.token_b_to_value(swap_source_amount, RoundDirection::Floor)?,
+                    swap_destination_amount,
+                    trade_direction,
+                )?;
+                self.apply_spread(result.destination_amount_swapped)?
+            }
+        };
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped: map_zero_to_none(destination_amount_swapped)?,
+        })
+    }
+
+    /// Stable exact-out swap on the token A values, grossing up the output by
+    /// the spread
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let source_amount_swapped = match trade_direction {
+            TradeDirection::AtoB => {
+                let destination_value =
+                    self.token_b_to_value(destination_amount, RoundDirection::Ceiling)?;
+                let result = self.stable_curve().swap_without_fees_exact_out(
+                    self.remove_spread(destination_value)?,
+                    swap_source_amount,
+                    self.token_b_to_value(swap_destination_amount, RoundDirection::Floor)?,
+                    trade_direction,
+                )?;
+                result.source_amount_swapped
+            }
+            TradeDirection::BtoA => {
+                let result = self.stable_curve().swap_without_fees_exact_out(
+                    self.remove_spread(destination_amount)?,
+                    self.token_b_to_value(swap_source_amount, RoundDirection::Floor)?,
+                    swap_destination_amount,
+                    trade_direction,
+                )?;
+                self.value_to_token_b(result.source_amount_swapped, RoundDirection::Ceiling)?
+            }
+        };
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: map_zero_to_none(source_amount_swapped)?,
+            destination_amount_swapped: map_zero_to_none(destination_amount)?,
+        })
+    }
+
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            round_direction,
+        )
+    }
+
+    /// Stable curve deposit of the token A value
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let source_value = match trade_direction {
+            TradeDirection::AtoB => source_amount,
+            TradeDirection::BtoA => self.token_b_to_value(source_amount, RoundDirection::Floor)?,
+        };
+        self.stable_curve().deposit_single_token_type(
+            source_value,
+            swap_token_a_amount,
+            self.token_b_to_value(swap_token_b_amount, RoundDirection::Floor)?,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    /// Stable curve withdrawal of the token A value
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let source_value = match trade_direction {
+            TradeDirection::AtoB => source_amount,
+            TradeDirection::BtoA => {
+                self.token_b_to_value(source_amount, RoundDirection::Ceiling)?
+            }
+        };
+        self.stable_curve().withdraw_single_token_type_exact_out(
+            source_value,
+            swap_token_a_amount,
+            self.token_b_to_value(swap_token_b_amount, RoundDirection::Floor)?,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        self.stable_curve().validate()?;
+        if self.spread >= SPREAD_DENOMINATOR {
+            return Err(SwapError::InvalidCurve);
+        }
+        Ok(())
+    }
+
+    /// The spot price is the one of the stable curve on the token A values,
+    /// converted back to token B at the oracle price
+    fn spot_price(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        self.stable_curve()
+            .spot_price(
+                swap_token_a_amount,
+                self.token_b_to_value(swap_token_b_amount, RoundDirection::Floor)?,
+            )?
+            .checked_mul(&PreciseNumber::new(self.oracle_price as u128)?)?
+            .checked_div(&PreciseNumber::new(ORACLE_PRICE_DENOMINATOR as u128)?)
+    }
+
+    /// The normalized value is the one of the stable curve on the token A
+    /// values, so it is expressed in token A.
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        self.stable_curve().normalized_value(
+            swap_token_a_amount,
+            self.token_b_to_value(swap_token_b_amount, RoundDirection::Floor)?,
+        )
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for OraclePeggedCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for OraclePeggedCurve {}
+impl Pack for OraclePeggedCurve {
+    const LEN: usize = 16;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<OraclePeggedCurve, ProgramError> {
+        let input = array_ref![input, 0, 16];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (amp, spread) = array_refs![input, 8, 8];
+        Ok(Self {
+            amp: u64::from_le_bytes(*amp),
+            spread: u64::from_le_bytes(*spread),
+            oracle_price: 0,
+        })
+    }
+}
+
+impl DynPack for OraclePeggedCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 16];
+        let (amp, spread) = mut_array_refs![output, 8, 8];
+        *amp = self.amp.to_le_bytes();
+        *spread = self.spread.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Token B is worth 2 token A, with a 0.3% spread
+    fn curve() -> OraclePeggedCurve {
+        OraclePeggedCurve {
+            amp: 100,
+            spread: 30,
+            oracle_price: 2 * ORACLE_PRICE_DENOMINATOR,
+        }
+    }
+
+    #[test]
+    fn pack_curve() {
+        let curve = OraclePeggedCurve {
+            oracle_price: 0,
+            ..curve()
+        };
+
+        let mut packed = [0u8; OraclePeggedCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = OraclePeggedCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    #[test]
+    fn validate_curve() {
+        assert!(curve().validate().is_ok());
+        assert!(OraclePeggedCurve { amp: 0, ..curve() }.validate().is_err());
+        assert!(OraclePeggedCurve {
+            spread: SPREAD_DENOMINATOR,
+            ..curve()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn swap_quotes_around_oracle_price() {
+        // balanced pool at the oracle price
+        let swap_token_a_amount: u128 = 2_000_000;
+        let swap_token_b_amount: u128 = 1_000_000;
+
+        let result = curve()
+            .swap_without_fees(1_000, swap_token_a_amount, swap_token_b_amount, TradeDirection::AtoB)
+            .unwrap();
+        // 1_000 token A buys close to 500 token B, less the 0.3% spread
+        assert!(result.destination_amount_swapped <= 498);
+        assert!(result.destination_amount_swapped >= 497);
+
+        let result = curve()
+            .swap_without_fees(1_000, swap_token_b_amount, swap_token_a_amount, TradeDirection::BtoA)
+            .unwrap();
+        assert!(result.destination_amount_swapped <= 1_994);
+        assert!(result.destination_amount_swapped >= 1_993);
+    }
+
+    #[test]
+    fn swap_without_price_fails() {
+        let curve = OraclePeggedCurve {
+            oracle_price: 0,
+            ..curve()
+        };
+        assert!(curve
+            .swap_without_fees(1_000, 2_000_000, 1_000_000, TradeDirection::AtoB)
+            .is_none());
+    }
+
+    #[test]
+    fn swap_exact_out_inverts_swap() {
+        let swap_token_a_amount: u128 = 2_000_000;
+        let swap_token_b_amount: u128 = 1_000_000;
+        let result = curve()
+            .swap_without_fees_exact_out(
+                497,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        let forward = curve()
+            .swap_without_fees(
+                result.source_amount_swapped,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert!(forward.destination_amount_swapped >= 497);
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            source_token_amount in 1..u32::MAX as u64,
+            swap_token_a_amount in 1..u32::MAX as u64,
+            swap_token_b_amount in 1..u32::MAX as u64,
+        ) {
+            let curve = curve();
+            let source_token_amount = source_token_amount as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            let results = curve.swap_without_fees(
+                source_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            );
+            // tiny swaps can give nothing back, which is rejected
+            if let Some(results) = results {
+                let previous_value = curve
+                    .normalized_value(swap_token_a_amount, swap_token_b_amount)
+                    .unwrap();
+                let new_value = curve
+                    .normalized_value(
+                        swap_token_a_amount + results.source_amount_swapped,
+                        swap_token_b_amount - results.destination_amount_swapped,
+                    )
+                    .unwrap();
+                prop_assert!(new_value.greater_than_or_equal(&previous_value));
+            }
+        }
+    }
+}
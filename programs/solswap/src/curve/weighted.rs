@@ -0,0 +1,904 @@
+//! Balancer-style weighted pool curve, token_a ^ weight_a * token_b ^ weight_b = constant
+
+use {
+    crate::{
+        curve::{
+            calculator::{
+                map_zero_to_none, CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult,
+                TradeDirection, TradingTokenResult,
+            },
+            constant_product::pool_tokens_to_trading_tokens,
+        },
+        SwapError,
+    },
+    arrayref::{array_mut_ref, array_ref},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::precise_number::PreciseNumber,
+};
+
+/// Denominator of the token weights, so that the weights are expressed in
+/// basis points and the two weights always add up to this value
+pub const WEIGHT_DENOMINATOR: u64 = 10_000;
+
+/// Smallest weight allowed for either token, 1%.  Smaller weights make the
+/// exponents in the swap calculations large enough to overflow.
+pub const MIN_WEIGHT: u64 = 100;
+
+/// Largest amount that a single swap or deposit can add to one side of the
+/// pool, as a fraction of that side's balance, 1/2 as in Balancer
+pub const MAX_IN_RATIO_NUMERATOR: u128 = 1;
+/// Denominator of the maximum in ratio
+pub const MAX_IN_RATIO_DENOMINATOR: u128 = 2;
+
+/// Largest amount that a single swap or withdrawal can take from one side of
+/// the pool, as a fraction of that side's balance, 1/3 as in Balancer
+pub const MAX_OUT_RATIO_NUMERATOR: u128 = 1;
+/// Denominator of the maximum out ratio
+pub const MAX_OUT_RATIO_DENOMINATOR: u128 = 3;
+
+/// Maximum number of terms of the binomial series used to approximate
+/// fractional powers.  With the trade ratios capped, the bases stay within
+/// `[2/3, 3/2]` and the series converges in well under this many terms.
+const MAX_POW_ITERATIONS: u128 = 100;
+
+/// Relative error tolerated from the power approximation, 1e-10.  It is always
+/// charged against the trader or depositor so that the pool never loses value
+/// to the approximation.
+fn pow_tolerance() -> Option<PreciseNumber> {
+    PreciseNumber::new(1)?.checked_div(&PreciseNumber::new(10_000_000_000)?)
+}
+
+/// Checks that `amount` is at most `numerator / denominator` of `balance`
+fn within_ratio(amount: u128, balance: u128, numerator: u128, denominator: u128) -> Option<()> {
+    if amount.checked_mul(denominator)? > balance.checked_mul(numerator)? {
+        None
+    } else {
+        Some(())
+    }
+}
+
+/// Approximates `base ^ exponent` for a fractional exponent below 1 and a base
+/// in the range `(0, 2)`, using the binomial series
+/// `(1 + x) ^ e = 1 + e * x + e * (e - 1) * x ^ 2 / 2! + ...`
+///
+/// The series is summed until its terms fall below the precision of
+/// `PreciseNumber`.  It converges slowly for bases near 0 or 2, so `None` is
+/// returned if it has not converged after `MAX_POW_ITERATIONS` terms, rather
+/// than an inaccurate result.
+///
+/// Reference at:
+/// <https://github.com/balancer-labs/balancer-core/blob/f4ed5d65362a8d6cec21662fb6eae233b0babc1f/contracts/BNum.sol#L128>
+fn checked_pow_approximation(base: &PreciseNumber, exponent: &PreciseNumber) -> Option<PreciseNumber> {
+    let one = PreciseNumber::new(1)?;
+    let zero = PreciseNumber::new(0)?;
+    let (x, x_negative) = base.unsigned_sub(&one);
+    let mut term = one.clone();
+    let mut sum = one;
+    let mut negative = false;
+
+    for i in 1..=MAX_POW_ITERATIONS {
+        let big_k = PreciseNumber::new(i)?;
+        let (c, c_negative) = exponent.unsigned_sub(&PreciseNumber::new(i.checked_sub(1)?)?);
+        term = term.checked_mul(&c.checked_mul(&x)?)?.checked_div(&big_k)?;
+        if !term.greater_than(&zero) {
+            return Some(sum);
+        }
+        if x_negative {
+            negative = !negative;
+        }
+        if c_negative {
+            negative = !negative;
+        }
+        sum = if negative {
+            sum.checked_sub(&term)?
+        } else {
+            sum.checked_add(&term)?
+        };
+    }
+    None
+}
+
+/// Calculates `base ^ exponent` for any non-negative base and exponent.
+///
+/// The whole part of the exponent is calculated exactly, and the fractional
+/// part is approximated.  Bases above 3/2 are first reduced by successive
+/// square roots to keep the approximation quickly convergent.  Bases below 1
+/// are used as they are, so the approximation fails for bases much smaller
+/// than 1/2.
+pub fn checked_pow_fraction(base: &PreciseNumber, exponent: &PreciseNumber) -> Option<PreciseNumber> {
+    let zero = PreciseNumber::new(0)?;
+    if !base.greater_than(&zero) {
+        return Some(zero);
+    }
+    let two = PreciseNumber::new(2)?;
+    let max_base = PreciseNumber::new(3)?.checked_div(&two)?;
+    let mut base = base.clone();
+    let mut exponent = exponent.clone();
+    while base.greater_than(&max_base) {
+        base = base.sqrt()?;
+        exponent = exponent.checked_mul(&two)?;
+    }
+    let whole = exponent.floor()?;
+    let fraction = exponent.checked_sub(&whole)?;
+    let whole_pow = base.checked_pow(whole.to_imprecise()?)?;
+    if !fraction.greater_than(&zero) {
+        return Some(whole_pow);
+    }
+    whole_pow.checked_mul(&checked_pow_approximation(&base, &fraction)?)
+}
+
+/// WeightedCurve struct implementing CurveCalculator
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeightedCurve {
+    /// Weight of token A in basis points, token B has the remaining weight
+    pub token_a_weight: u64,
+}
+
+impl WeightedCurve {
+    /// Weights of the source and destination tokens for the given direction
+    fn weights(&self, trade_direction: TradeDirection) -> Option<(PreciseNumber, PreciseNumber)> {
+        let token_a_weight = PreciseNumber::new(self.token_a_weight as u128)?;
+        let token_b_weight =
+            PreciseNumber::new(WEIGHT_DENOMINATOR.checked_sub(self.token_a_weight)? as u128)?;
+        match trade_direction {
+            TradeDirection::AtoB => Some((token_a_weight, token_b_weight)),
+            TradeDirection::BtoA => Some((token_b_weight, token_a_weight)),
+        }
+    }
+
+    /// Normalized weight, between 0 and 1, of the source token for the given
+    /// direction
+    fn normalized_source_weight(&self, trade_direction: TradeDirection) -> Option<PreciseNumber> {
+        let (source_weight, _) = self.weights(trade_direction)?;
+        source_weight.checked_div(&PreciseNumber::new(WEIGHT_DENOMINATOR as u128)?)
+    }
+}
+
+/// An even 50/50 weighting, equivalent to the constant product curve
+impl Default for WeightedCurve {
+    fn default() -> Self {
+        Self {
+            token_a_weight: WEIGHT_DENOMINATOR / 2,
+        }
+    }
+}
+
+impl CurveCalculator for WeightedCurve {
+    /// Weighted swap, giving
+    /// `destination = swap_destination * (1 - (swap_source / (swap_source + source)) ^ (weight_source / weight_destination))`
+    ///
+    /// The source amount is capped by `MAX_IN_RATIO_NUMERATOR / MAX_IN_RATIO_DENOMINATOR`.
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        within_ratio(
+            source_amount,
+            swap_source_amount,
+            MAX_IN_RATIO_NUMERATOR,
+            MAX_IN_RATIO_DENOMINATOR,
+        )?;
+        let (source_weight, destination_weight) = self.weights(trade_direction)?;
+        let exponent = source_weight.checked_div(&destination_weight)?;
+
+        let new_swap_source_amount = swap_source_amount.checked_add(source_amount)?;
+        let base = PreciseNumber::new(swap_source_amount)?
+            .checked_div(&PreciseNumber::new(new_swap_source_amount)?)?;
+        let ratio = checked_pow_fraction(&base, &exponent)?.checked_add(&pow_tolerance()?)?;
+        let ratio = PreciseNumber::new(1)?.checked_sub(&ratio)?;
+
+        let destination_amount_swapped = PreciseNumber::new(swap_destination_amount)?
+            .checked_mul(&ratio)?
+            .floor()?
+            .to_imprecise()?;
+        let destination_amount_swapped = map_zero_to_none(std::cmp::min(
+            destination_amount_swapped,
+            swap_destination_amount.checked_sub(1)?,
+        ))?;
+
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped,
+        })
+    }
+
+    /// Weighted swap for an exact output amount, giving
+    /// `source = swap_source * ((swap_destination / (swap_destination - destination)) ^ (weight_destination / weight_source) - 1)`
+    ///
+    /// The destination amount is capped by `MAX_OUT_RATIO_NUMERATOR / MAX_OUT_RATIO_DENOMINATOR`.
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        within_ratio(
+            destination_amount,
+            swap_destination_amount,
+            MAX_OUT_RATIO_NUMERATOR,
+            MAX_OUT_RATIO_DENOMINATOR,
+        )?;
+        let (source_weight, destination_weight) = self.weights(trade_direction)?;
+        let exponent = destination_weight.checked_div(&source_weight)?;
+
+        let new_swap_destination_amount =
+            map_zero_to_none(swap_destination_amount.checked_sub(destination_amount)?)?;
+        let base = PreciseNumber::new(swap_destination_amount)?
+            .checked_div(&PreciseNumber::new(new_swap_destination_amount)?)?;
+        // the power can be large, so the tolerance is charged relative to it
+        let one = PreciseNumber::new(1)?;
+        let ratio = checked_pow_fraction(&base, &exponent)?
+            .checked_mul(&one.checked_add(&pow_tolerance()?)?)?
+            .checked_sub(&one)?;
+
+        let source_amount_swapped = PreciseNumber::new(swap_source_amount)?
+            .checked_mul(&ratio)?
+            .ceiling()?
+            .to_imprecise()?;
+        let source_amount_swapped = map_zero_to_none(source_amount_swapped)?;
+        let destination_amount_swapped = map_zero_to_none(destination_amount)?;
+
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        })
+    }
+
+    /// Pool tokens always represent a proportional share of both sides, no
+    /// matter the weights
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            round_direction,
+        )
+    }
+
+    /// Get the amount of pool tokens for the deposited amount of token A or B,
+    /// using the Balancer formula
+    /// `pool_supply * ((1 + source / swap_source) ^ weight_source - 1)`
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let swap_source_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => swap_token_b_amount,
+        };
+        within_ratio(
+            source_amount,
+            swap_source_amount,
+            MAX_IN_RATIO_NUMERATOR,
+            MAX_IN_RATIO_DENOMINATOR,
+        )?;
+        let one = PreciseNumber::new(1)?;
+        let ratio = PreciseNumber::new(source_amount)?
+            .checked_div(&PreciseNumber::new(swap_source_amount)?)?;
+        let base = one.checked_add(&ratio)?;
+        let root = checked_pow_fraction(&base, &self.normalized_source_weight(trade_direction)?)?;
+        let (root, negative) = root.unsigned_sub(&one.checked_add(&pow_tolerance()?)?);
+        if negative {
+            return Some(0);
+        }
+        let pool_tokens = PreciseNumber::new(pool_supply)?.checked_mul(&root)?;
+        pool_tokens.floor()?.to_imprecise()
+    }
+
+    /// Get the amount of pool tokens for the withdrawn amount of token A or B,
+    /// using the Balancer formula
+    /// `pool_supply * (1 - (1 - source / swap_source) ^ weight_source)`
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let swap_source_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => swap_token_b_amount,
+        };
+        if source_amount == 0 {
+            return Some(0);
+        }
+        within_ratio(
+            source_amount,
+            swap_source_amount,
+            MAX_OUT_RATIO_NUMERATOR,
+            MAX_OUT_RATIO_DENOMINATOR,
+        )?;
+        let one = PreciseNumber::new(1)?;
+        let ratio = PreciseNumber::new(source_amount)?
+            .checked_div(&PreciseNumber::new(swap_source_amount)?)?;
+        let base = one.checked_sub(&ratio)?;
+        let root = checked_pow_fraction(&base, &self.normalized_source_weight(trade_direction)?)?;
+        let (root, negative) = one.unsigned_sub(&root);
+        let root = if negative {
+            pow_tolerance()?
+        } else {
+            root.checked_add(&pow_tolerance()?)?
+        };
+        let pool_tokens = PreciseNumber::new(pool_supply)?.checked_mul(&root)?;
+        pool_tokens.ceiling()?.to_imprecise()
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.token_a_weight < MIN_WEIGHT
+            || self.token_a_weight > WEIGHT_DENOMINATOR - MIN_WEIGHT
+        {
+            Err(SwapError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The spot price of the weighted curve is the ratio of the token amounts,
+    /// each divided by its weight, `(token_a / weight_a) / (token_b / weight_b)`
+    fn spot_price(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let token_a_weight = self.token_a_weight as u128;
+        let token_b_weight = (WEIGHT_DENOMINATOR as u128).checked_sub(token_a_weight)?;
+        PreciseNumber::new(swap_token_a_amount.checked_mul(token_b_weight)?)?.checked_div(
+            &PreciseNumber::new(map_zero_to_none(
+                swap_token_b_amount.checked_mul(token_a_weight)?,
+            )?)?,
+        )
+    }
+
+    /// The normalized value of the weighted curve is the weighted geometric
+    /// mean of the token amounts, `token_a ^ weight_a * token_b ^ weight_b`,
+    /// with the weights normalized to add up to 1.
+    ///
+    /// To keep the base of the power small, this is calculated as
+    /// `smaller * (larger / smaller) ^ weight_larger`.
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let (smaller, larger, larger_weight) = if swap_token_a_amount <= swap_token_b_amount {
+            (
+                swap_token_a_amount,
+                swap_token_b_amount,
+                self.normalized_source_weight(TradeDirection::BtoA)?,
+            )
+        } else {
+            (
+                swap_token_b_amount,
+                swap_token_a_amount,
+                self.normalized_source_weight(TradeDirection::AtoB)?,
+            )
+        };
+        if smaller == 0 {
+            return PreciseNumber::new(0);
+        }
+        let smaller = PreciseNumber::new(smaller)?;
+        let ratio = PreciseNumber::new(larger)?.checked_div(&smaller)?;
+        smaller.checked_mul(&checked_pow_fraction(&ratio, &larger_weight)?)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for WeightedCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for WeightedCurve {}
+impl Pack for WeightedCurve {
+    const LEN: usize = 8;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<WeightedCurve, ProgramError> {
+        let token_a_weight = array_ref![input, 0, 8];
+        Ok(Self {
+            token_a_weight: u64::from_le_bytes(*token_a_weight),
+        })
+    }
+}
+
+impl DynPack for WeightedCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let token_a_weight = array_mut_ref![output, 0, 8];
+        *token_a_weight = self.token_a_weight.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{
+        calculator::test::{
+            check_curve_value_from_swap_exact_out, check_pool_value_from_deposit,
+            check_pool_value_from_withdraw,
+        },
+        constant_product::ConstantProductCurve,
+    };
+    use proptest::prelude::*;
+
+    #[test]
+    fn pack_curve() {
+        let token_a_weight = 8_000;
+        let curve = WeightedCurve { token_a_weight };
+
+        let mut packed = [0u8; WeightedCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = WeightedCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&token_a_weight.to_le_bytes());
+        let unpacked = WeightedCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    #[test]
+    fn validate_weights() {
+        assert!(WeightedCurve { token_a_weight: 0 }.validate().is_err());
+        assert!(WeightedCurve { token_a_weight: 99 }.validate().is_err());
+        assert!(WeightedCurve { token_a_weight: 100 }.validate().is_ok());
+        assert!(WeightedCurve { token_a_weight: 8_000 }.validate().is_ok());
+        assert!(WeightedCurve { token_a_weight: 9_900 }.validate().is_ok());
+        assert!(WeightedCurve { token_a_weight: 9_901 }.validate().is_err());
+        assert!(WeightedCurve {
+            token_a_weight: WEIGHT_DENOMINATOR
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn spot_price_accounts_for_weights() {
+        let curve = WeightedCurve {
+            token_a_weight: 8_000,
+        };
+        // 80% of the value is in 4_000 token A, 20% in 1_000 token B, so
+        // token B is worth 1 token A
+        let price = curve.spot_price(4_000, 1_000).unwrap();
+        assert_eq!(price.to_imprecise().unwrap(), 1);
+        let price = curve.spot_price(8_000, 1_000).unwrap();
+        assert_eq!(price.to_imprecise().unwrap(), 2);
+    }
+
+    #[test]
+    fn pow_fraction() {
+        let base = PreciseNumber::new(16).unwrap();
+        let exponent = PreciseNumber::new(1)
+            .unwrap()
+            .checked_div(&PreciseNumber::new(4).unwrap())
+            .unwrap();
+        let result = checked_pow_fraction(&base, &exponent).unwrap();
+        assert_eq!(result.to_imprecise().unwrap(), 2);
+
+        let base = PreciseNumber::new(1)
+            .unwrap()
+            .checked_div(&PreciseNumber::new(2).unwrap())
+            .unwrap();
+        let exponent = PreciseNumber::new(3).unwrap();
+        let result = checked_pow_fraction(&base, &exponent).unwrap();
+        let result = result
+            .checked_mul(&PreciseNumber::new(1_000).unwrap())
+            .unwrap();
+        assert_eq!(result.to_imprecise().unwrap(), 125);
+
+        // the series does not converge fast enough for bases close to 0
+        let base = PreciseNumber::new(1)
+            .unwrap()
+            .checked_div(&PreciseNumber::new(1_000).unwrap())
+            .unwrap();
+        let exponent = PreciseNumber::new(1)
+            .unwrap()
+            .checked_div(&PreciseNumber::new(100).unwrap())
+            .unwrap();
+        assert!(checked_pow_fraction(&base, &exponent).is_none());
+    }
+
+    #[test]
+    fn trade_ratios_capped() {
+        let curve = WeightedCurve::default();
+        let swap_source_amount: u128 = 1_000_000;
+        let swap_destination_amount: u128 = 3_000_000;
+        assert!(curve
+            .swap_without_fees(
+                500_000,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB
+            )
+            .is_some());
+        assert!(curve
+            .swap_without_fees(
+                500_001,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB
+            )
+            .is_none());
+        assert!(curve
+            .swap_without_fees_exact_out(
+                1_000_000,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB
+            )
+            .is_some());
+        assert!(curve
+            .swap_without_fees_exact_out(
+                1_000_001,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB
+            )
+            .is_none());
+        assert!(curve
+            .deposit_single_token_type(
+                500_001,
+                swap_source_amount,
+                swap_destination_amount,
+                1_000_000,
+                TradeDirection::AtoB
+            )
+            .is_none());
+        // withdrawing almost all of one side would burn too few pool tokens
+        // if it were allowed
+        assert!(curve
+            .withdraw_single_token_type_exact_out(
+                999_000,
+                swap_source_amount,
+                swap_destination_amount,
+                1_000_000,
+                TradeDirection::AtoB
+            )
+            .is_none());
+        assert!(curve
+            .withdraw_single_token_type_exact_out(
+                333_333,
+                swap_source_amount,
+                swap_destination_amount,
+                1_000_000,
+                TradeDirection::AtoB
+            )
+            .is_some());
+    }
+
+    #[test]
+    fn even_weights_match_constant_product() {
+        let curve = WeightedCurve::default();
+        let constant_product = ConstantProductCurve {};
+        let swap_source_amount: u128 = 1_000;
+        let swap_destination_amount: u128 = 50_000;
+        let source_amount: u128 = 100;
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        let expected = constant_product
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, source_amount);
+        assert!(result.destination_amount_swapped <= expected.destination_amount_swapped);
+        assert!(result.destination_amount_swapped + 1 >= expected.destination_amount_swapped);
+    }
+
+    #[test]
+    fn swap_weighted() {
+        // 80/20 pool, so token A is worth a quarter as much per token when
+        // the balances are equal
+        let curve = WeightedCurve {
+            token_a_weight: 8_000,
+        };
+        let swap_source_amount: u128 = 1_000_000;
+        let swap_destination_amount: u128 = 1_000_000;
+        let source_amount: u128 = 1_000;
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        // 1_000_000 * (1 - (1_000_000 / 1_001_000) ^ 4) = 3_990.01...
+        assert_eq!(result.destination_amount_swapped, 3_990);
+
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        // 1_000_000 * (1 - (1_000_000 / 1_001_000) ^ 0.25) = 249.84...
+        assert_eq!(result.destination_amount_swapped, 249);
+    }
+
+    #[test]
+    fn swap_exact_out_inverts_swap() {
+        let curve = WeightedCurve {
+            token_a_weight: 8_000,
+        };
+        let swap_source_amount: u128 = 1_000_000;
+        let swap_destination_amount: u128 = 1_000_000;
+        let result = curve
+            .swap_without_fees_exact_out(
+                3_990,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, 3_990);
+        let forward = curve
+            .swap_without_fees(
+                result.source_amount_swapped,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert!(forward.destination_amount_swapped >= 3_990);
+
+        // cannot empty the destination side
+        assert!(curve
+            .swap_without_fees_exact_out(
+                swap_destination_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            source_token_amount in 1..u32::MAX as u64,
+            swap_source_amount in 1_000..u32::MAX as u64,
+            swap_destination_amount in 1_000..u32::MAX as u64,
+            token_a_weight in MIN_WEIGHT..WEIGHT_DENOMINATOR - MIN_WEIGHT,
+        ) {
+            let curve = WeightedCurve { token_a_weight };
+            let source_token_amount = source_token_amount as u128;
+            let swap_source_amount = swap_source_amount as u128;
+            let swap_destination_amount = swap_destination_amount as u128;
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                let results = curve.swap_without_fees(
+                    source_token_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    trade_direction,
+                );
+                // tiny swaps can give nothing back, which is rejected
+                if let Some(results) = results {
+                    let (swap_token_a_amount, swap_token_b_amount, new_swap_token_a_amount, new_swap_token_b_amount) = match trade_direction {
+                        TradeDirection::AtoB => (
+                            swap_source_amount,
+                            swap_destination_amount,
+                            swap_source_amount + results.source_amount_swapped,
+                            swap_destination_amount - results.destination_amount_swapped,
+                        ),
+                        TradeDirection::BtoA => (
+                            swap_destination_amount,
+                            swap_source_amount,
+                            swap_destination_amount - results.destination_amount_swapped,
+                            swap_source_amount + results.source_amount_swapped,
+                        ),
+                    };
+                    let previous_value = curve
+                        .normalized_value(swap_token_a_amount, swap_token_b_amount)
+                        .unwrap();
+                    let new_value = curve
+                        .normalized_value(new_swap_token_a_amount, new_swap_token_b_amount)
+                        .unwrap();
+                    prop_assert!(new_value.greater_than_or_equal(&previous_value));
+                }
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_deposit(
+            pool_token_amount in 1..u32::MAX as u64,
+            pool_token_supply in 1..u32::MAX as u64,
+            swap_token_a_amount in 1..u32::MAX as u64,
+            swap_token_b_amount in 1..u32::MAX as u64,
+            token_a_weight in MIN_WEIGHT..WEIGHT_DENOMINATOR - MIN_WEIGHT,
+        ) {
+            let curve = WeightedCurve { token_a_weight };
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            check_pool_value_from_deposit(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_single_sided_deposit(
+            source_token_amount in 1..u32::MAX as u64,
+            pool_token_supply in 1_000..u32::MAX as u64,
+            swap_token_a_amount in 1_000..u32::MAX as u64,
+            swap_token_b_amount in 1_000..u32::MAX as u64,
+            token_a_weight in MIN_WEIGHT..WEIGHT_DENOMINATOR - MIN_WEIGHT,
+        ) {
+            let curve = WeightedCurve { token_a_weight };
+            let source_token_amount = source_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            prop_assume!(source_token_amount * MAX_IN_RATIO_DENOMINATOR <= swap_token_a_amount * MAX_IN_RATIO_NUMERATOR);
+            let pool_token_amount = curve
+                .deposit_single_token_type(
+                    source_token_amount,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    pool_token_supply,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            let value = curve
+                .normalized_value(swap_token_a_amount, swap_token_b_amount)
+                .unwrap();
+            let new_value = curve
+                .normalized_value(swap_token_a_amount + source_token_amount, swap_token_b_amount)
+                .unwrap();
+
+            // the value per pool token must not go down:
+            // new_value * pool_token_supply >= value * new_pool_token_supply
+            let new_pool_token_supply =
+                PreciseNumber::new(pool_token_supply + pool_token_amount).unwrap();
+            let pool_token_supply = PreciseNumber::new(pool_token_supply).unwrap();
+            prop_assert!(new_value
+                .checked_mul(&pool_token_supply)
+                .unwrap()
+                .greater_than_or_equal(&value.checked_mul(&new_pool_token_supply).unwrap()));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_withdraw(
+            pool_token_amount in 1..u32::MAX as u64,
+            pool_token_supply in 1..u32::MAX as u64,
+            swap_token_a_amount in 1..u32::MAX as u64,
+            swap_token_b_amount in 1..u32::MAX as u64,
+            token_a_weight in MIN_WEIGHT..WEIGHT_DENOMINATOR - MIN_WEIGHT,
+        ) {
+            let curve = WeightedCurve { token_a_weight };
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            prop_assume!(pool_token_amount < pool_token_supply);
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            check_pool_value_from_withdraw(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_single_sided_withdraw_near_limit(
+            out_ratio_thousandths in 900..=1_000u128,
+            pool_token_supply in 1_000..u32::MAX as u64,
+            swap_token_a_amount in 3_000..u32::MAX as u64,
+            swap_token_b_amount in 1_000..u32::MAX as u64,
+            token_a_weight in MIN_WEIGHT..WEIGHT_DENOMINATOR - MIN_WEIGHT,
+        ) {
+            let curve = WeightedCurve { token_a_weight };
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            let source_token_amount = swap_token_a_amount * MAX_OUT_RATIO_NUMERATOR * out_ratio_thousandths
+                / (MAX_OUT_RATIO_DENOMINATOR * 1_000);
+            let pool_token_amount = curve
+                .withdraw_single_token_type_exact_out(
+                    source_token_amount,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    pool_token_supply,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            prop_assert!(pool_token_amount < pool_token_supply);
+            let value = curve
+                .normalized_value(swap_token_a_amount, swap_token_b_amount)
+                .unwrap();
+            let new_value = curve
+                .normalized_value(swap_token_a_amount - source_token_amount, swap_token_b_amount)
+                .unwrap();
+
+            // the value per pool token must not go down:
+            // new_value * pool_token_supply >= value * new_pool_token_supply
+            let new_pool_token_supply =
+                PreciseNumber::new(pool_token_supply - pool_token_amount).unwrap();
+            let pool_token_supply = PreciseNumber::new(pool_token_supply).unwrap();
+            prop_assert!(new_value
+                .checked_mul(&pool_token_supply)
+                .unwrap()
+                .greater_than_or_equal(&value.checked_mul(&new_pool_token_supply).unwrap()));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_exact_out_swap_near_limit(
+            out_ratio_thousandths in 900..=1_000u128,
+            swap_source_amount in 1_000..u32::MAX as u64,
+            swap_destination_amount in 3_000..u32::MAX as u64,
+            // normalized_value itself loses precision at more extreme weights
+            token_a_weight in 1_000..WEIGHT_DENOMINATOR - 1_000,
+        ) {
+            let curve = WeightedCurve { token_a_weight };
+            let swap_source_amount = swap_source_amount as u128;
+            let swap_destination_amount = swap_destination_amount as u128;
+            let destination_token_amount = swap_destination_amount * MAX_OUT_RATIO_NUMERATOR * out_ratio_thousandths
+                / (MAX_OUT_RATIO_DENOMINATOR * 1_000);
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_curve_value_from_swap_exact_out(
+                    &curve,
+                    destination_token_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    trade_direction,
+                );
+            }
+        }
+    }
+}
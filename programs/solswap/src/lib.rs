@@ -1,24 +1,62 @@
+// `into_*_context` builders follow the Anchor naming convention for CPI
+// contexts, and amounts are widened with `try_from` throughout, as in the
+// SPL token swap program this is derived from
+#![allow(clippy::wrong_self_convention, clippy::unnecessary_fallible_conversions)]
+
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::program_option::COption;
-use anchor_spl::token::{self, Burn, Mint, MintTo, TokenAddress, Transfer};
+use anchor_lang::Discriminator;
+use anchor_lang::solana_program::{
+    bpf_loader_upgradeable,
+    hash::hash,
+    instruction::{AccountMeta, Instruction},
+    program::{invoke, set_return_data},
+    program_option::COption,
+    program_pack::Pack,
+};
+use anchor_spl::token::{self, Burn, Mint, MintTo, TokenAccount, Transfer};
+use spl_math::{precise_number::PreciseNumber, uint::U256};
 use curve::base::CurveType;
 use std::convert::TryFrom;
 
 pub mod curve;
 
 use crate::curve:: {
-    base::SwapCurve,
+    base::{SwapCurve, SwapResult},
     calculator::{CurveCalculator, RoundDirection, TradeDirection},
-    fees::CurveFees,
+    fees::{calculate_fee, CurveFees},
 };
 
 use crate::curve::{
+    concentrated_liquidity::ConcentratedLiquidityCurve,
     constant_price::ConstantPriceCurve,
+    constant_product::ConstantProductCurve,
+    liquidity_bootstrapping::LiquidityBootstrappingCurve,
     offset::OffsetCurve,
+    oracle_pegged::OraclePeggedCurve,
+    stable::{StableCurve, MAX_AMP_CHANGE, MIN_RAMP_DURATION},
+    weighted::WeightedCurve,
 };
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
+/// Denominator of limits expressed in basis points
+pub const BPS_DENOMINATOR: u64 = 10_000;
+/// Seed of the program-wide global config address
+pub const GLOBAL_CONFIG_SEED: &[u8] = b"global_config";
+/// Maximum number of valid curve types in the global config
+pub const MAX_VALID_CURVE_TYPES: usize = 16;
+/// Maximum number of valid token programs in the global config
+pub const MAX_VALID_TOKEN_PROGRAMS: usize = 4;
+/// Fixed-point scale of the prices summed into the cumulative prices
+pub const CUMULATIVE_PRICE_SCALE: u128 = 1_000_000_000_000;
+/// Number of observations kept by an observations account
+pub const OBSERVATIONS_LEN: usize = 64;
+/// Maximum number of timestamps queried by a single `observe`
+pub const MAX_OBSERVE_QUERIES: usize = 16;
+/// Preimage of the discriminator of the instruction called on flash loan
+/// receivers, matching an Anchor `receive_flash_loan(amount: u64, fee: u64)`
+/// instruction
+pub const FLASH_LOAN_RECEIVER_PREIMAGE: &[u8] = b"global:receive_flash_loan";
 
 #[program]
 pub mod solswap {
@@ -27,8 +65,9 @@ pub mod solswap {
     //function that initialize the swap pool
     pub fn initialize(
         ctx: Context<Initialize>,
-        //fees_input: FeeInput,
-        curve_offset_input: CurveInput,
+        fees_input: CurveFees,
+        curve_input: CurveInput,
+        fee_timelock_slots: u64,
     ) -> Result<()> {
         if ctx.accounts.solswap.is_initialized {
             return Err(SwapError::AlreadyInUse.into());
@@ -45,19 +84,19 @@ pub mod solswap {
         ];
 
         if *ctx.accounts.authority.key != swap_authority {
-            return Err(SwapError::InvalidProgramAddress.info());
+            return Err(SwapError::InvalidProgramAddress.into());
         }
 
-        if *ctx.account.authority.key != ctx.accounts.token_a.owner {
-            return Error(SwapError::InvalidOwner.into());
+        if *ctx.accounts.authority.key != ctx.accounts.token_a.owner {
+            return Err(SwapError::InvalidOwner.into());
         }
 
-        if *ctx.account.authority.key != ctx.accounts.token_b.owner {
-            return Error(SwapError::InvalidOwner.into());
+        if *ctx.accounts.authority.key != ctx.accounts.token_b.owner {
+            return Err(SwapError::InvalidOwner.into());
         }
 
-        if *ctx.accounts.authority.key == ctx.destination.owner {
-            return Error(SwapError::InvalidOutputOwner.into());
+        if *ctx.accounts.authority.key == ctx.accounts.destination.owner {
+            return Err(SwapError::InvalidOutputOwner.into());
         }
 
         if COption::Some(*ctx.accounts.authority.key) != ctx.accounts.pool_mint.mint_authority {
@@ -68,16 +107,33 @@ pub mod solswap {
             return Err(SwapError::RepeatedMint.into());
         }
 
-        let curve = build_curve(&curve_input).unwrap();
+        if *ctx.accounts.pool_mint.to_account_info().key != ctx.accounts.fee_account.mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+
+        let mut curve_input = curve_input;
+        let curve_type = CurveType::try_from(curve_input.curve_type)
+            .map_err(|_| SwapError::UnsupportedCurveType)?;
+        if curve_type == CurveType::Stable {
+            // stable pools start without any amplification coefficient ramp
+            let timestamp = Clock::get()?.unix_timestamp;
+            curve_input.curve_schedule = CurveSchedule {
+                end_curve_parameters: curve_input.curve_parameters,
+                start_timestamp: timestamp,
+                end_timestamp: timestamp,
+            };
+        }
+
+        let curve = load_curve(&curve_input, ctx.remaining_accounts)?;
         curve
             .calculator
             .validate_supply(ctx.accounts.token_a.amount, ctx.accounts.token_b.amount)?;
 
-        if ctx.accounts.token_a_delegate.is_some() {
+        if ctx.accounts.token_a.delegate.is_some() {
             return Err(SwapError::InvalidDelegate.into());
         }
 
-        if ctx.accounts.token_b_delegate.is_some() {
+        if ctx.accounts.token_b.delegate.is_some() {
             return Err(SwapError::InvalidDelegate.into());
         }
 
@@ -97,18 +153,18 @@ pub mod solswap {
             return Err(SwapError::InvalidFreezeAuthority.into());
         }
 
-        if let Some(swap_constraints) = SWAP_CONSTRAINTS {
-            let owner_key = swap_constraints
-                .owner_key
-                .parse::<Pubkey>()
-                .map_err(|_| SwapError::InvalidOwner)?;
-            if ctx.accouts.fee_account.owner != owner_key {
-                return Err(SwapError::InvalidOwner.into());
-            }
-            swap_constraints.validate_curve(&curve);
+        let swap_constraints = &ctx.accounts.global_config.constraints;
+        if swap_constraints.owner_key != Pubkey::default()
+            && ctx.accounts.fee_account.owner != swap_constraints.owner_key
+        {
+            return Err(SwapError::InvalidOwner.into());
         }
+        swap_constraints.validate_curve(&curve)?;
+        swap_constraints.validate_fees(&fees_input)?;
+        swap_constraints.validate_token_program(ctx.accounts.token_program.key)?;
 
         curve.calculator.validate()?;
+        fees_input.validate()?;
 
         let initial_amount = curve.calculator.new_pool_supply();
         
@@ -117,7 +173,7 @@ pub mod solswap {
                 .into_mint_to_context()
                 .with_signer(&[&seeds[..]]),
             u64::try_from(initial_amount).unwrap(),
-        );
+        )?;
 
         let solswap = &mut ctx.accounts.solswap;
         solswap.is_initialized = true;
@@ -126,109 +182,161 @@ pub mod solswap {
         solswap.token_a_account = *ctx.accounts.token_a.to_account_info().key;
         solswap.token_b_account = *ctx.accounts.token_b.to_account_info().key;
         solswap.pool_mint = *ctx.accounts.pool_mint.to_account_info().key;
+        solswap.pool_fee_account = *ctx.accounts.fee_account.to_account_info().key;
         solswap.token_a_mint = ctx.accounts.token_a.mint;
         solswap.token_b_mint = ctx.accounts.token_b.mint;
-        solswap.curve = curve;
-
-        Ok(());
+        solswap.curve = curve_input;
+        solswap.fees = fees_input;
+        solswap.last_price_update_slot = Clock::get()?.slot;
+        solswap.admin = *ctx.accounts.admin.key;
+        solswap.fee_timelock_slots = fee_timelock_slots;
+        solswap.cumulative_price_timestamp = Clock::get()?.unix_timestamp;
+
+        emit!(PoolInitialized {
+            pool: *solswap.to_account_info().key,
+            token_a_mint: solswap.token_a_mint,
+            token_b_mint: solswap.token_b_mint,
+            pool_mint: solswap.pool_mint,
+            curve_type: solswap.curve.curve_type,
+            token_a_amount: ctx.accounts.token_a.amount,
+            token_b_amount: ctx.accounts.token_b.amount,
+            pool_token_amount: u64::try_from(initial_amount).unwrap(),
+        });
+
+        Ok(())
     }
 
-    pub fn swap(ctx: Context<Swap>, amount_in: u64, minimum_amount_out: u64) -> Result<()> {
-        let solswap = &mut ctx.accounts.solswap;
-
-        if solswap.to_account_info().owner != ctx.program_id {
-            return Err(ProgramError::IncorrectProgramId.into());
-        }
-
-        if *ctx.accounts.authority.key != authority_id(ctx.program_id, solswap.to_account_info().key, solswap.bump_seed)? {
-            return Err(SwapError::InvalidProgramAddress.into());
-        }
-
-        if !(*ctx.accounts.swap_source.to_account_info().key == solswap.token_a_account 
-            || *ctx.accounts.swap_source.to_account_info().key == solswap.token_b_account) {
-                return Err(SwapError::IncorrectSwapAccount.into());
-        }
-
-        if !(*ctx.accounts.swap_destination.to_account_info().key == solswap.token_a_account 
-            || *ctx.accounts.swap_destination.to_account_info().key == solswap.token_b_account) {
-                return Err(SwapError::IncorrectSwapAccount.into());
-        }
-
-        if *ctx.accounts.swap_source.to_account_info().key == *ctx.accounts.swap_destination.to_account_info().key {
-            return Err(SwapError::InvalidInput.into());
-        }
-
-        if *ctx.accounts.swap_source.to_account_info().key == ctx.accounts.source_info.key {
-            return Err(SwapError::InvalidInput.into());
-        }
-
-        if *ctx.accounts.swap_destination.to_account_info().key == ctx.accounts.destination_info.key {
-            return Err(SwapError::InvalidInput.into());
-        }
-
-        if *ctx.token_program.key != solswap.token_program_id {
-            return Err(SwapError::IncorrectTokenProgramId.into());
-        }
+    pub fn swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, Swap<'info>>,
+        amount_in: u64,
+        minimum_amount_out: u64,
+    ) -> Result<()> {
+        let trade_direction = check_swap_accounts(ctx.program_id, ctx.accounts)?;
+        let curve = load_curve(&ctx.accounts.solswap.curve, ctx.remaining_accounts)?;
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (
+                ctx.accounts.swap_source.amount,
+                ctx.accounts.swap_destination.amount,
+            ),
+            TradeDirection::BtoA => (
+                ctx.accounts.swap_destination.amount,
+                ctx.accounts.swap_source.amount,
+            ),
+        };
+        update_cumulative_prices(
+            &mut ctx.accounts.solswap,
+            &curve,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            ctx.remaining_accounts,
+        )?;
+        let solswap = &ctx.accounts.solswap;
 
-        let trade_direction = 
-            if *ctx.accounts.swap_source.to_account_info().key == solswap.token_a_account{
-                TradeDirection::AtoB
-            } else {
-                TradeDirection::BtoA
-            };
-        let curve =  build_curve(&solswap.curve).unwrap();
-        
         let result = curve 
             .swap(
                 u128::try_from(amount_in).unwrap(),
                 u128::try_from(ctx.accounts.swap_source.amount).unwrap(),
                 u128::try_from(ctx.accounts.swap_destination.amount).unwrap(),
                 trade_direction,
+                solswap.active_fees(Clock::get()?.slot),
             )
             .ok_or(SwapError::ZeroTradingTokens)?;
         if result.destination_amount_swapped < u128::try_from(minimum_amount_out).unwrap() {
             return Err(SwapError::ExceededSlippage.into());
         }
 
+        settle_swap(&ctx, &curve, &result, trade_direction)
+    }
+
+    pub fn swap_exact_out<'info>(
+        ctx: Context<'_, '_, '_, 'info, Swap<'info>>,
+        amount_out: u64,
+        maximum_amount_in: u64,
+    ) -> Result<()> {
+        let trade_direction = check_swap_accounts(ctx.program_id, ctx.accounts)?;
+        let curve = load_curve(&ctx.accounts.solswap.curve, ctx.remaining_accounts)?;
         let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
-            TradeDirection::AtoB (
-                result.new_swap_source_amount,
-                result.new_swap_destination_amount,
+            TradeDirection::AtoB => (
+                ctx.accounts.swap_source.amount,
+                ctx.accounts.swap_destination.amount,
+            ),
+            TradeDirection::BtoA => (
+                ctx.accounts.swap_destination.amount,
+                ctx.accounts.swap_source.amount,
             ),
-            TradeDirection::BtoA (
-                result.new_swap_destination_amount,
-                result.new_swap_source_amount
-            )
         };
+        update_cumulative_prices(
+            &mut ctx.accounts.solswap,
+            &curve,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            ctx.remaining_accounts,
+        )?;
+        let solswap = &ctx.accounts.solswap;
 
-        let seeds = &[&solswap.to_account_info().key.to_bytes(), &[solswap.bump_seed][..]];
+        let result = curve
+            .swap_exact_out(
+                u128::try_from(amount_out).unwrap(),
+                u128::try_from(ctx.accounts.swap_source.amount).unwrap(),
+                u128::try_from(ctx.accounts.swap_destination.amount).unwrap(),
+                trade_direction,
+                solswap.active_fees(Clock::get()?.slot),
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        if result.source_amount_swapped > u128::try_from(maximum_amount_in).unwrap() {
+            return Err(SwapError::ExceededSlippage.into());
+        }
 
-        token::transfer(
-            ctx.accounts
-                .into_transfer_to_swap_source_context()
-                .with_signer(&[&seeds[..]]),
-            u64::try_from(result.source_amount_swapped).unwrap(),
-        )?;
+        settle_swap(&ctx, &curve, &result, trade_direction)
+    }
 
-        token::transfer(
-            ctx.accounts
-                .into_transfer_to_destination_context()
-                .with_signer(&[&seeds[..]]),
-            u64::try_from(result.destination_amount_swapped).unwrap(),
+    /// Calculates the result of swapping `amount_in` without moving any tokens,
+    /// writing the serialized `SwapResult` as return data
+    pub fn quote_swap(ctx: Context<QuoteSwap>, amount_in: u64) -> Result<()> {
+        let solswap = &ctx.accounts.solswap;
+        let trade_direction = check_pool_swap_accounts(
+            solswap,
+            ctx.program_id,
+            &ctx.accounts.authority,
+            &ctx.accounts.swap_source.to_account_info(),
+            &ctx.accounts.swap_destination.to_account_info(),
+            &ctx.accounts.pool_mint.to_account_info(),
+            &ctx.accounts.pool_fee_account.to_account_info(),
+            &ctx.accounts.token_program,
         )?;
+        let curve = load_curve(&solswap.curve, ctx.remaining_accounts)?;
+
+        let result = curve
+            .swap(
+                u128::try_from(amount_in).unwrap(),
+                u128::try_from(ctx.accounts.swap_source.amount).unwrap(),
+                u128::try_from(ctx.accounts.swap_destination.amount).unwrap(),
+                trade_direction,
+                solswap.active_fees(Clock::get()?.slot),
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+
+        set_return_data(&result.try_to_vec()?);
+
+        Ok(())
     }
 
     pub fn deposit_all_token_types(
-        ctx: Context<depositAllTokenTypes>,
+        ctx: Context<DepositAllTokenTypes>,
         pool_token_amount: u64,
         maximum_token_a_amount: u64,
         maximum_token_b_amount: u64,
     ) ->  Result<()> {
-        let solswap = &mut ctx.accounts.solswap;
+        let solswap = &ctx.accounts.solswap;
+        if solswap.deposits_paused {
+            return Err(SwapError::PoolPaused.into());
+        }
+        if solswap.flash_loan_in_progress {
+            return Err(SwapError::FlashLoanInProgress.into());
+        }
 
-        let curve = build_curve(&solswap.curve).unwrap();
-        let calculator = curve.calculator;
-        if !calculator.allows_deposits() {
+        let curve = load_curve(&solswap.curve, ctx.remaining_accounts)?;
+        if !curve.calculator.allows_deposits() {
             return Err(SwapError::UnsupportedCurveOperation.into());
         }
 
@@ -246,6 +354,16 @@ pub mod solswap {
             None,
         )?;
 
+        update_cumulative_prices(
+            &mut ctx.accounts.solswap,
+            &curve,
+            ctx.accounts.token_a.amount,
+            ctx.accounts.token_b.amount,
+            ctx.remaining_accounts,
+        )?;
+        let solswap = &ctx.accounts.solswap;
+        let calculator = curve.calculator;
+
         let current_pool_mint_supply = u128::try_from(ctx.accounts.pool_mint.supply).unwrap();
         let (pool_token_amount, pool_mint_supply) = if current_pool_mint_supply > 0 {
             (
@@ -270,10 +388,6 @@ pub mod solswap {
         if token_a_amount > maximum_token_a_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
-        
-        if token_a_amount == 0 {
-            return Err(SwapError::ZeroTradingTokens.into());
-        }
 
         let token_b_amount = u64::try_from(results.token_b_amount).unwrap();
 
@@ -281,52 +395,68 @@ pub mod solswap {
             return Err(SwapError::ExceededSlippage.into());
         }
 
-        if token_b_amount == 0 {
-            return Err(SwapError::ZeroTradingTokens.into());
-        }
+        check_trading_token_amounts(
+            token_a_amount,
+            token_b_amount,
+            ctx.accounts.token_a.amount,
+            ctx.accounts.token_b.amount,
+        )?;
 
         let pool_token_amount = u64::try_from(pool_token_amount).unwrap();
 
         let seeds = &[&solswap.to_account_info().key.to_bytes(), &[solswap.bump_seed][..]];
 
-        token::transfer(
-            ctx.Accounts    
-                .into_transfer_to_token_a_context()
-                .with_signer(&[seeds[..]]),
-            token_a_amount,
-        );
+        if token_a_amount > 0 {
+            token::transfer(
+                ctx.accounts.into_transfer_to_token_a_context(),
+                token_a_amount,
+            )?;
+        }
 
-        token::transfer(
-            ctx.Accounts    
-                .into_transfer_to_token_b_context()
-                .with_signer(&[seeds[..]]),
-            token_b_amount,
-        );
+        if token_b_amount > 0 {
+            token::transfer(
+                ctx.accounts.into_transfer_to_token_b_context(),
+                token_b_amount,
+            )?;
+        }
 
         token::mint_to(
-            ctx.Accounts    
+            ctx.accounts
                 .into_mint_to_context()
-                .with_signer(&[seeds[..]]),
-            u64::try_from(pool_token_amount).unwrap(),
-        );
+                .with_signer(&[&seeds[..]]),
+            pool_token_amount,
+        )?;
+
+        emit!(DepositEvent {
+            pool: *solswap.to_account_info().key,
+            token_a_amount,
+            token_b_amount,
+            pool_token_amount,
+        });
 
-        Ok(());
+        Ok(())
     }
 
     pub fn withdraw_all_token_types(
-        ctx: Context<depositAllTokenTypes>,
+        ctx: Context<WithdrawAllTokenTypes>,
         pool_token_amount: u64,
-        maximum_token_a_amount: u64,
-        maximum_token_b_amount: u64,
+        minimum_token_a_amount: u64,
+        minimum_token_b_amount: u64,
     ) ->  Result<()> {
-        let solswap = &mut ctx.accounts.solswap;
-
-        let curve = build_curve(&solswap.curve).unwrap();
-        
-        let calculator = curve.calculator;
+        let solswap = &ctx.accounts.solswap;
+        if solswap.withdrawals_paused {
+            return Err(SwapError::PoolPaused.into());
+        }
+        if solswap.flash_loan_in_progress {
+            return Err(SwapError::FlashLoanInProgress.into());
+        }
 
-        if !calculator.allows_deposites() {
-            return Err(SwapError::UnsupportedCurveOperation.info());
+        // proportional withdrawals don't depend on the price, so they don't
+        // read the oracle of an oracle pegged pool, and stay open when its
+        // price feed goes stale
+        let curve = build_curve(&solswap.curve, Clock::get()?.unix_timestamp, 0)?;
+        if !curve.calculator.allows_deposits() {
+            return Err(SwapError::UnsupportedCurveOperation.into());
         }
 
         check_accounts(
@@ -340,11 +470,40 @@ pub mod solswap {
             &ctx.accounts.token_program,
             Some(&ctx.accounts.dest_token_a_info),
             Some(&ctx.accounts.dest_token_b_info),
+            Some(&ctx.accounts.pool_fee_account),
         )?;
 
-        let pool_token_amount = u128::try_from(pool_token_amount).unwrap().ok_or(SwapError::CalculationFailure)?;
+        // nor do they move the price, so the cumulative prices of an oracle
+        // pegged pool, which can't be priced without its oracle, are left for
+        // the next priced operation to bring up to date
+        if curve.curve_type != CurveType::OraclePegged {
+            update_cumulative_prices(
+                &mut ctx.accounts.solswap,
+                &curve,
+                ctx.accounts.token_a.amount,
+                ctx.accounts.token_b.amount,
+                ctx.remaining_accounts,
+            )?;
+        }
+        let solswap = &ctx.accounts.solswap;
+        let calculator = curve.calculator;
+
+        let withdraw_fee: u128 =
+            if *ctx.accounts.pool_fee_account.key == *ctx.accounts.source_info.key {
+                // withdrawing from the fee account, don't assess withdraw fee
+                0
+            } else {
+                solswap
+                    .active_fees(Clock::get()?.slot)
+                    .owner_withdraw_fee(u128::try_from(pool_token_amount).unwrap())
+                    .ok_or(SwapError::FeeCalculationFailure)?
+            };
+        let pool_token_amount = u128::try_from(pool_token_amount)
+            .unwrap()
+            .checked_sub(withdraw_fee)
+            .ok_or(SwapError::CalculationFailure)?;
 
-        let result = calculator
+        let results = calculator
             .pool_tokens_to_trading_tokens(
                 pool_token_amount,
                 u128::try_from(ctx.accounts.pool_mint.supply).unwrap(),
@@ -355,28 +514,35 @@ pub mod solswap {
             .ok_or(SwapError::ZeroTradingTokens)?;
         
         let token_a_amount = u64::try_from(results.token_a_amount).unwrap();
-        let minimum_token_a_amount = std::cmp::min(ctx.accounts.token_a.amount, token_a_amount);
+        let token_a_amount = std::cmp::min(ctx.accounts.token_a.amount, token_a_amount);
         if token_a_amount < minimum_token_a_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
-        if token_a_amount == 0 && ctx.accounts.token_a.amount != 0 {
-            return Err(SwapError::ZeroTradingTokens.into());
-        }
         let token_b_amount = u64::try_from(results.token_b_amount).unwrap();
-        let minimum_token_b_amount = std::cmp::min(ctx.accounts.token_b.amount, token_b_amount);
-        if token_a_amount < minimum_token_a_amount {
+        let token_b_amount = std::cmp::min(ctx.accounts.token_b.amount, token_b_amount);
+        if token_b_amount < minimum_token_b_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
-        if token_a_amount == 0 && ctx.accounts.token_a.amount != 0 {
-            return Err(SwapError::ZeroTradingTokens.into());
-        }
+        check_trading_token_amounts(
+            token_a_amount,
+            token_b_amount,
+            ctx.accounts.token_a.amount,
+            ctx.accounts.token_b.amount,
+        )?;
+
+        let seeds = &[&solswap.to_account_info().key.to_bytes(), &[solswap.bump_seed][..]];
 
-        let seeds = &[&solswap.to_account_info().key.to_bytes(), &[solswap.bump_seed][..]]
+        if withdraw_fee > 0 {
+            token::transfer(
+                ctx.accounts.into_transfer_to_pool_fee_context(),
+                u64::try_from(withdraw_fee).unwrap(),
+            )?;
+        }
 
         token::burn(
             ctx.accounts.into_burn_context(),
             u64::try_from(pool_token_amount).unwrap(),
-        );
+        )?;
 
         if token_a_amount > 0 {
             token::transfer(
@@ -396,96 +562,833 @@ pub mod solswap {
             )?;
         }
 
-        Ok(());
+        emit!(WithdrawEvent {
+            pool: *solswap.to_account_info().key,
+            token_a_amount,
+            token_b_amount,
+            pool_token_amount: u64::try_from(pool_token_amount).unwrap(),
+            withdraw_fee: u64::try_from(withdraw_fee).unwrap(),
+        });
 
+        Ok(())
     }
 
-    pub fn deposit_single_token_type(
-        ctx: Context<depositAllTokenTypes>,
-        pool_token_amount: u64,
-        maximum_token_a_amount: u64,
-        maximum_token_b_amount: u64,
-    ) ->  Result<()> {
-        
-    }
+    pub fn deposit_single_token_type_exact_amount_in(
+        ctx: Context<DepositSingleTokenType>,
+        source_token_amount: u64,
+        minimum_pool_token_amount: u64,
+    ) -> Result<()> {
+        let solswap = &ctx.accounts.solswap;
+        if solswap.deposits_paused {
+            return Err(SwapError::PoolPaused.into());
+        }
+        if solswap.flash_loan_in_progress {
+            return Err(SwapError::FlashLoanInProgress.into());
+        }
 
-    pub fn withdraw_single_token_type(
-        ctx: Context<depositAllTokenTypes>,
-        pool_token_amount: u64,
-        maximum_token_a_amount: u64,
-        maximum_token_b_amount: u64,
-    ) ->  Result<()> {
-        
-    }
-}
+        let curve = load_curve(&solswap.curve, ctx.remaining_accounts)?;
+        if !curve.calculator.allows_deposits() {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
 
+        let trade_direction = if ctx.accounts.source.mint == ctx.accounts.token_a.mint {
+            TradeDirection::AtoB
+        } else if ctx.accounts.source.mint == ctx.accounts.token_b.mint {
+            TradeDirection::BtoA
+        } else {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        };
 
-#[derive(Accounts)]
+        let source_info = ctx.accounts.source.to_account_info();
+        let (source_a_info, source_b_info) = match trade_direction {
+            TradeDirection::AtoB => (Some(&source_info), None),
+            TradeDirection::BtoA => (None, Some(&source_info)),
+        };
 
-pub struct Initialize<'info> {
-    //CHECK : Safe
-    pub authority: AccountInfo<'info>,
-    #[account(signer, zero)]
-    pub solswap: Box<Account<'info, Solswap>>,
-    #[account(mut)]
-    pub pool_mint: Account<'info, Mint>,
-    #[account(mut)]
-    pub token_a: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub token_b: Account<'info, TokenAccount>,
-    //#[account(mut)]
-    // pub fee_account: Account<'info, TokenAccount>
-    #[account(mut)]
-    pub destination: Account<'info, TokenAccount>,
+        check_accounts(
+            solswap,
+            ctx.program_id,
+            &solswap.to_account_info(),
+            &ctx.accounts.authority,
+            &ctx.accounts.token_a.to_account_info(),
+            &ctx.accounts.token_b.to_account_info(),
+            &ctx.accounts.pool_mint.to_account_info(),
+            &ctx.accounts.token_program,
+            source_a_info,
+            source_b_info,
+            None,
+        )?;
 
-    //CHECK : Safe
-    pub token_program: AccountInfo<'info>,
-}
+        update_cumulative_prices(
+            &mut ctx.accounts.solswap,
+            &curve,
+            ctx.accounts.token_a.amount,
+            ctx.accounts.token_b.amount,
+            ctx.remaining_accounts,
+        )?;
+        let solswap = &ctx.accounts.solswap;
+
+        let pool_mint_supply = u128::try_from(ctx.accounts.pool_mint.supply).unwrap();
+        let pool_token_amount = if pool_mint_supply > 0 {
+            curve
+                .deposit_single_token_type(
+                    u128::try_from(source_token_amount).unwrap(),
+                    u128::try_from(ctx.accounts.token_a.amount).unwrap(),
+                    u128::try_from(ctx.accounts.token_b.amount).unwrap(),
+                    pool_mint_supply,
+                    trade_direction,
+                    solswap.active_fees(Clock::get()?.slot),
+                )
+                .ok_or(SwapError::ZeroTradingTokens)?
+        } else {
+            curve.calculator.new_pool_supply()
+        };
 
-#[derive(Accounts)]
+        let pool_token_amount = u64::try_from(pool_token_amount).unwrap();
+        if pool_token_amount < minimum_pool_token_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        if pool_token_amount == 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
 
-pub struct Swap<'info> {
-    /// CHECK: Safe
-    pub authority: AccountInfo<'info>,
-    pub solswap: Box<Account<'info, Solswap>>,
-    /// CHECK: Safe
-    #[account(signer)]
-    pub user_transfer_authority: AccountInfo<'info>,
-    /// CHECK: Safe
-    #[account(mut)]
-    pub source_info: AccountInfo<'info>,
-    /// CHECK: Safe
-    #[account(mut)]
-    pub destination_info: AccountInfo<'info>,
+        let seeds = &[&solswap.to_account_info().key.to_bytes(), &[solswap.bump_seed][..]];
 
-    #[account(mut)]
-    pub swap_source: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub swap_destination: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub pool_mint: Account<'info, Mint>,
-    
-    pub token_program: AccountInfo<'info>,
-    
-}
+        match trade_direction {
+            TradeDirection::AtoB => {
+                token::transfer(
+                    ctx.accounts.into_transfer_to_token_a_context(),
+                    source_token_amount,
+                )?;
+            }
+            TradeDirection::BtoA => {
+                token::transfer(
+                    ctx.accounts.into_transfer_to_token_b_context(),
+                    source_token_amount,
+                )?;
+            }
+        }
 
-#[derive(Accounts)]
-pub struct DepositAllTokenTypes<'info> {
-    pub solswap: Box<Account<'info, Solswap>>,
-    /// CHECK: Safe
-    pub authority: AccountInfo<'info,
-    /// CHECK: Safe
-    #[account(signer)]
-    pub user_transfer_authority_info: AccountInfo<'info>,
-    /// CHECK: Safe
-    #[account(mut)]
-    pub source_a_info: AccountInfo<'info>,
-    /// CHECK: Safe
-    #[account(mut)]
-    pub source_b_info: AccountInfo<'info>,
-    #[account(mut)]
-    pub token_a: Account<'info, TokenAccount>,
-    #[account(mut)]
+        token::mint_to(
+            ctx.accounts
+                .into_mint_to_context()
+                .with_signer(&[&seeds[..]]),
+            pool_token_amount,
+        )?;
+
+        let (token_a_amount, token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (source_token_amount, 0),
+            TradeDirection::BtoA => (0, source_token_amount),
+        };
+        emit!(DepositEvent {
+            pool: *solswap.to_account_info().key,
+            token_a_amount,
+            token_b_amount,
+            pool_token_amount,
+        });
+
+        Ok(())
+    }
+
+    pub fn withdraw_single_token_type_exact_amount_out(
+        ctx: Context<WithdrawSingleTokenType>,
+        destination_token_amount: u64,
+        maximum_pool_token_amount: u64,
+    ) -> Result<()> {
+        let solswap = &ctx.accounts.solswap;
+        if solswap.withdrawals_paused {
+            return Err(SwapError::PoolPaused.into());
+        }
+        if solswap.flash_loan_in_progress {
+            return Err(SwapError::FlashLoanInProgress.into());
+        }
+
+        let trade_direction = if ctx.accounts.destination.mint == ctx.accounts.token_a.mint {
+            TradeDirection::AtoB
+        } else if ctx.accounts.destination.mint == ctx.accounts.token_b.mint {
+            TradeDirection::BtoA
+        } else {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        };
+
+        let destination_info = ctx.accounts.destination.to_account_info();
+        let (destination_a_info, destination_b_info) = match trade_direction {
+            TradeDirection::AtoB => (Some(&destination_info), None),
+            TradeDirection::BtoA => (None, Some(&destination_info)),
+        };
+
+        check_accounts(
+            solswap,
+            ctx.program_id,
+            &solswap.to_account_info(),
+            &ctx.accounts.authority,
+            &ctx.accounts.token_a.to_account_info(),
+            &ctx.accounts.token_b.to_account_info(),
+            &ctx.accounts.pool_mint.to_account_info(),
+            &ctx.accounts.token_program,
+            destination_a_info,
+            destination_b_info,
+            Some(&ctx.accounts.pool_fee_account),
+        )?;
+
+        let curve = load_curve(&solswap.curve, ctx.remaining_accounts)?;
+        update_cumulative_prices(
+            &mut ctx.accounts.solswap,
+            &curve,
+            ctx.accounts.token_a.amount,
+            ctx.accounts.token_b.amount,
+            ctx.remaining_accounts,
+        )?;
+        let solswap = &ctx.accounts.solswap;
+
+        let burn_pool_token_amount = curve
+            .withdraw_single_token_type_exact_out(
+                u128::try_from(destination_token_amount).unwrap(),
+                u128::try_from(ctx.accounts.token_a.amount).unwrap(),
+                u128::try_from(ctx.accounts.token_b.amount).unwrap(),
+                u128::try_from(ctx.accounts.pool_mint.supply).unwrap(),
+                trade_direction,
+                solswap.active_fees(Clock::get()?.slot),
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+
+        let withdraw_fee: u128 =
+            if *ctx.accounts.pool_fee_account.key == *ctx.accounts.source_info.key {
+                // withdrawing from the fee account, don't assess withdraw fee
+                0
+            } else {
+                solswap
+                    .active_fees(Clock::get()?.slot)
+                    .owner_withdraw_fee(burn_pool_token_amount)
+                    .ok_or(SwapError::FeeCalculationFailure)?
+            };
+        let pool_token_amount = burn_pool_token_amount
+            .checked_add(withdraw_fee)
+            .ok_or(SwapError::CalculationFailure)?;
+
+        if u64::try_from(pool_token_amount).unwrap() > maximum_pool_token_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        if pool_token_amount == 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+
+        let seeds = &[&solswap.to_account_info().key.to_bytes(), &[solswap.bump_seed][..]];
+
+        if withdraw_fee > 0 {
+            token::transfer(
+                ctx.accounts.into_transfer_to_pool_fee_context(),
+                u64::try_from(withdraw_fee).unwrap(),
+            )?;
+        }
+
+        token::burn(
+            ctx.accounts.into_burn_context(),
+            u64::try_from(burn_pool_token_amount).unwrap(),
+        )?;
+
+        match trade_direction {
+            TradeDirection::AtoB => {
+                token::transfer(
+                    ctx.accounts
+                        .into_transfer_from_token_a_context()
+                        .with_signer(&[&seeds[..]]),
+                    destination_token_amount,
+                )?;
+            }
+            TradeDirection::BtoA => {
+                token::transfer(
+                    ctx.accounts
+                        .into_transfer_from_token_b_context()
+                        .with_signer(&[&seeds[..]]),
+                    destination_token_amount,
+                )?;
+            }
+        }
+
+        let (token_a_amount, token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (destination_token_amount, 0),
+            TradeDirection::BtoA => (0, destination_token_amount),
+        };
+        emit!(WithdrawEvent {
+            pool: *solswap.to_account_info().key,
+            token_a_amount,
+            token_b_amount,
+            pool_token_amount: u64::try_from(burn_pool_token_amount).unwrap(),
+            withdraw_fee: u64::try_from(withdraw_fee).unwrap(),
+        });
+
+        Ok(())
+    }
+
+    /// Sets a new price on a constant price pool.  The price can only move by
+    /// a bounded amount per call, and calls must be spaced out by a minimum
+    /// number of slots.
+    pub fn set_constant_price(ctx: Context<SetConstantPrice>, token_b_price: u64) -> Result<()> {
+        let solswap = &ctx.accounts.solswap;
+        let curve_type = CurveType::try_from(solswap.curve.curve_type)
+            .map_err(|_| SwapError::UnsupportedCurveType)?;
+        if curve_type != CurveType::ConstantPrice {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        check_admin(solswap, &ctx.accounts.admin)?;
+
+        let clock = Clock::get()?;
+        let price_update = &solswap.curve.price_update;
+        let next_update_slot = solswap
+            .last_price_update_slot
+            .checked_add(price_update.min_slot_interval)
+            .ok_or(SwapError::CalculationFailure)?;
+        if clock.slot < next_update_slot {
+            return Err(SwapError::PriceUpdateTooFrequent.into());
+        }
+
+        let current_price = u128::from(solswap.curve.curve_parameters);
+        let new_price = u128::from(token_b_price);
+        let price_change = new_price.abs_diff(current_price);
+        let max_price_change = current_price
+            .checked_mul(u128::from(price_update.max_change_bps))
+            .ok_or(SwapError::CalculationFailure)?;
+        if price_change
+            .checked_mul(u128::from(BPS_DENOMINATOR))
+            .ok_or(SwapError::CalculationFailure)?
+            > max_price_change
+        {
+            return Err(SwapError::ExcessivePriceChange.into());
+        }
+
+        let curve = ConstantPriceCurve { token_b_price };
+        curve.validate()?;
+        curve.validate_supply(ctx.accounts.token_a.amount, ctx.accounts.token_b.amount)?;
+
+        let solswap = &mut ctx.accounts.solswap;
+        solswap.curve.curve_parameters = token_b_price;
+        solswap.last_price_update_slot = clock.slot;
+
+        Ok(())
+    }
+
+    /// Starts moving the amplification coefficient of a stable pool linearly
+    /// from its current value to `target_amp`, reached at `stop_ramp_ts`
+    pub fn ramp_amp(ctx: Context<RampAmp>, target_amp: u64, stop_ramp_ts: i64) -> Result<()> {
+        let solswap = &ctx.accounts.solswap;
+        let curve_type = CurveType::try_from(solswap.curve.curve_type)
+            .map_err(|_| SwapError::UnsupportedCurveType)?;
+        if curve_type != CurveType::Stable {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        check_admin(solswap, &ctx.accounts.admin)?;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        let schedule = &solswap.curve.curve_schedule;
+        let next_ramp_timestamp = schedule
+            .start_timestamp
+            .checked_add(MIN_RAMP_DURATION)
+            .ok_or(SwapError::CalculationFailure)?;
+        if timestamp < next_ramp_timestamp {
+            return Err(SwapError::RampLocked.into());
+        }
+        let ramp_duration = stop_ramp_ts
+            .checked_sub(timestamp)
+            .ok_or(SwapError::CalculationFailure)?;
+        if ramp_duration < MIN_RAMP_DURATION {
+            return Err(SwapError::InvalidRampDuration.into());
+        }
+
+        StableCurve { amp: target_amp }.validate()?;
+        let current_amp = schedule
+            .curve_parameters_at(solswap.curve.curve_parameters, timestamp)
+            .ok_or(SwapError::CalculationFailure)?;
+        let max_amp = current_amp
+            .checked_mul(MAX_AMP_CHANGE)
+            .ok_or(SwapError::CalculationFailure)?;
+        let min_amp = target_amp
+            .checked_mul(MAX_AMP_CHANGE)
+            .ok_or(SwapError::CalculationFailure)?;
+        if target_amp > max_amp || current_amp > min_amp {
+            return Err(SwapError::ExcessiveAmpChange.into());
+        }
+
+        let solswap = &mut ctx.accounts.solswap;
+        solswap.curve.curve_parameters = current_amp;
+        solswap.curve.curve_schedule = CurveSchedule {
+            end_curve_parameters: target_amp,
+            start_timestamp: timestamp,
+            end_timestamp: stop_ramp_ts,
+        };
+
+        Ok(())
+    }
+
+    /// Stops an amplification coefficient ramp, freezing the coefficient at
+    /// its current value
+    pub fn stop_ramp_amp(ctx: Context<RampAmp>) -> Result<()> {
+        let solswap = &ctx.accounts.solswap;
+        let curve_type = CurveType::try_from(solswap.curve.curve_type)
+            .map_err(|_| SwapError::UnsupportedCurveType)?;
+        if curve_type != CurveType::Stable {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        check_admin(solswap, &ctx.accounts.admin)?;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        let current_amp = solswap
+            .curve
+            .curve_schedule
+            .curve_parameters_at(solswap.curve.curve_parameters, timestamp)
+            .ok_or(SwapError::CalculationFailure)?;
+
+        let solswap = &mut ctx.accounts.solswap;
+        solswap.curve.curve_parameters = current_amp;
+        solswap.curve.curve_schedule = CurveSchedule {
+            end_curve_parameters: current_amp,
+            start_timestamp: timestamp,
+            end_timestamp: timestamp,
+        };
+
+        Ok(())
+    }
+
+    /// Attaches an observations account to the pool, in which swaps record
+    /// the pool's cumulative prices and reserves
+    pub fn initialize_observations(ctx: Context<InitializeObservations>) -> Result<()> {
+        check_admin(&ctx.accounts.solswap, &ctx.accounts.admin)?;
+
+        let mut observations = ctx.accounts.observations.load_init()?;
+        observations.pool = *ctx.accounts.solswap.to_account_info().key;
+
+        let solswap = &mut ctx.accounts.solswap;
+        solswap.observations = *ctx.accounts.observations.to_account_info().key;
+
+        Ok(())
+    }
+
+    /// Returns the cumulative prices of the pool `seconds_ago` seconds before
+    /// now, interpolating between recorded observations, as a serialized
+    /// `Vec<ObservedPrices>` in return data
+    pub fn observe(ctx: Context<Observe>, seconds_ago: Vec<u32>) -> Result<()> {
+        if seconds_ago.len() > MAX_OBSERVE_QUERIES {
+            return Err(SwapError::TooManyObservationQueries.into());
+        }
+        let solswap = &ctx.accounts.solswap;
+        let observations = ctx.accounts.observations.load()?;
+        let curve = load_curve(&solswap.curve, ctx.remaining_accounts)?;
+
+        // cumulative prices at the current time, extrapolated from the last
+        // update with the current reserves
+        let timestamp = Clock::get()?.unix_timestamp;
+        let (cumulative_price_a, cumulative_price_b) = cumulative_prices_at(
+            solswap,
+            &curve,
+            ctx.accounts.token_a.amount,
+            ctx.accounts.token_b.amount,
+            timestamp,
+        )?;
+        let current = Observation {
+            cumulative_price_a,
+            cumulative_price_b,
+            timestamp,
+            swap_token_a_amount: ctx.accounts.token_a.amount,
+            swap_token_b_amount: ctx.accounts.token_b.amount,
+            padding: 0,
+        };
+        let latest = Observation {
+            cumulative_price_a: solswap.cumulative_price_a,
+            cumulative_price_b: solswap.cumulative_price_b,
+            timestamp: solswap.cumulative_price_timestamp,
+            ..current
+        };
+
+        let mut results = Vec::with_capacity(seconds_ago.len());
+        for seconds_ago in seconds_ago {
+            let target = timestamp
+                .checked_sub(i64::from(seconds_ago))
+                .ok_or(SwapError::CalculationFailure)?;
+            let (before, after) = if target >= latest.timestamp {
+                (&latest, &current)
+            } else {
+                observations
+                    .surrounding(target)
+                    .map(|(before, after)| (before, after.unwrap_or(&latest)))
+                    .ok_or(SwapError::ObservationTooOld)?
+            };
+            results.push(
+                interpolate_observations(before, after, target)
+                    .ok_or(SwapError::CalculationFailure)?,
+            );
+        }
+
+        set_return_data(&results.try_to_vec()?);
+
+        Ok(())
+    }
+
+    /// Lends `amount` of one of the pool's tokens to the destination account,
+    /// then calls the receiver program with the remaining accounts, passing
+    /// the amount and the fee.  The pool's balance of the token must have
+    /// grown by at least the fee, which stays with liquidity providers, once
+    /// the receiver returns.
+    ///
+    /// All the remaining accounts, in order and with their signer and
+    /// writable flags, are the accounts of the receiver's instruction; the
+    /// pool reads none of them.
+    pub fn flash_loan<'info>(
+        ctx: Context<'_, '_, '_, 'info, FlashLoan<'info>>,
+        amount: u64,
+        token: PoolToken,
+    ) -> Result<()> {
+        let solswap = &ctx.accounts.solswap;
+        if solswap.swaps_paused {
+            return Err(SwapError::PoolPaused.into());
+        }
+        if solswap.flash_loan_in_progress {
+            return Err(SwapError::FlashLoanInProgress.into());
+        }
+        if *ctx.accounts.authority.key
+            != authority_id(ctx.program_id, solswap.to_account_info().key, solswap.bump_seed)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if ctx.accounts.receiver_program.key == ctx.program_id
+            || !ctx.accounts.receiver_program.executable
+        {
+            return Err(SwapError::InvalidFlashLoanReceiver.into());
+        }
+
+        let vault = match token {
+            PoolToken::TokenA => &ctx.accounts.token_a,
+            PoolToken::TokenB => &ctx.accounts.token_b,
+        };
+        if ctx.accounts.destination.mint != vault.mint {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        let fee = calculate_fee(
+            u128::try_from(amount).unwrap(),
+            u128::try_from(solswap.flash_loan_fee_numerator).unwrap(),
+            u128::try_from(solswap.flash_loan_fee_denominator).unwrap(),
+        )
+        .ok_or(SwapError::FeeCalculationFailure)?;
+        let required_balance = u128::try_from(vault.amount)
+            .unwrap()
+            .checked_add(fee)
+            .ok_or(SwapError::CalculationFailure)?;
+        let fee = u64::try_from(fee).unwrap();
+        let vault_info = vault.to_account_info();
+
+        // lock the pool for the duration of the loan, writing the flag to the
+        // account so that the receiver cannot reenter the pool
+        ctx.accounts.solswap.flash_loan_in_progress = true;
+        ctx.accounts.solswap.exit(ctx.program_id)?;
+
+        let solswap = &ctx.accounts.solswap;
+        let seeds = &[&solswap.to_account_info().key.to_bytes(), &[solswap.bump_seed][..]];
+        token::transfer(
+            ctx.accounts
+                .into_transfer_to_destination_context(vault_info)
+                .with_signer(&[&seeds[..]]),
+            amount,
+        )?;
+
+        let mut data = hash(FLASH_LOAN_RECEIVER_PREIMAGE).to_bytes()[..8].to_vec();
+        data.extend_from_slice(&amount.to_le_bytes());
+        data.extend_from_slice(&fee.to_le_bytes());
+        let instruction = Instruction {
+            program_id: *ctx.accounts.receiver_program.key,
+            accounts: ctx
+                .remaining_accounts
+                .iter()
+                .map(|account| AccountMeta {
+                    pubkey: *account.key,
+                    is_signer: account.is_signer,
+                    is_writable: account.is_writable,
+                })
+                .collect(),
+            data,
+        };
+        let mut account_infos = ctx.remaining_accounts.to_vec();
+        account_infos.push(ctx.accounts.receiver_program.clone());
+        invoke(&instruction, &account_infos)?;
+
+        let vault = match token {
+            PoolToken::TokenA => &mut ctx.accounts.token_a,
+            PoolToken::TokenB => &mut ctx.accounts.token_b,
+        };
+        vault.reload()?;
+        if u128::try_from(vault.amount).unwrap() < required_balance {
+            return Err(SwapError::FlashLoanNotRepaid.into());
+        }
+
+        ctx.accounts.solswap.flash_loan_in_progress = false;
+
+        Ok(())
+    }
+
+    /// Sets the fee charged on flash loans, as a fraction of the amount lent
+    pub fn set_flash_loan_fee(
+        ctx: Context<SetFlashLoanFee>,
+        fee_numerator: u64,
+        fee_denominator: u64,
+    ) -> Result<()> {
+        check_admin(&ctx.accounts.solswap, &ctx.accounts.admin)?;
+        if fee_numerator >= fee_denominator && !(fee_numerator == 0 && fee_denominator == 0) {
+            return Err(SwapError::InvalidFee.into());
+        }
+
+        let solswap = &mut ctx.accounts.solswap;
+        solswap.flash_loan_fee_numerator = fee_numerator;
+        solswap.flash_loan_fee_denominator = fee_denominator;
+
+        Ok(())
+    }
+
+    /// Sets new fees for the pool.  If the pool has a fee timelock, the fees
+    /// are queued and only charged once the timelock has passed, so that
+    /// liquidity providers can exit before the change.
+    pub fn set_fees(ctx: Context<SetFees>, new_fees: CurveFees) -> Result<()> {
+        check_admin(&ctx.accounts.solswap, &ctx.accounts.admin)?;
+        new_fees.validate()?;
+        ctx.accounts
+            .global_config
+            .constraints
+            .validate_fees(&new_fees)?;
+
+        let slot = Clock::get()?.slot;
+        let solswap = &mut ctx.accounts.solswap;
+        // fees queued by a previous call take effect first if their timelock
+        // has passed
+        solswap.fees = solswap.active_fees(slot).clone();
+        if solswap.fee_timelock_slots == 0 {
+            solswap.fees = new_fees;
+            solswap.pending_fees = CurveFees::default();
+            solswap.pending_fees_slot = 0;
+        } else {
+            solswap.pending_fees = new_fees;
+            solswap.pending_fees_slot = slot
+                .checked_add(solswap.fee_timelock_slots)
+                .ok_or(SwapError::CalculationFailure)?;
+        }
+
+        Ok(())
+    }
+
+    /// Pauses or resumes swaps, deposits and withdrawals on the pool, each
+    /// independently, so that withdrawals can remain open while trading is
+    /// halted
+    pub fn set_pause(
+        ctx: Context<SetPause>,
+        swaps_paused: bool,
+        deposits_paused: bool,
+        withdrawals_paused: bool,
+    ) -> Result<()> {
+        check_admin(&ctx.accounts.solswap, &ctx.accounts.admin)?;
+
+        let solswap = &mut ctx.accounts.solswap;
+        solswap.swaps_paused = swaps_paused;
+        solswap.deposits_paused = deposits_paused;
+        solswap.withdrawals_paused = withdrawals_paused;
+
+        Ok(())
+    }
+
+    /// Proposes a new admin for the pool, who becomes admin once they accept
+    pub fn propose_admin(ctx: Context<ProposeAdmin>, new_admin: Pubkey) -> Result<()> {
+        check_admin(&ctx.accounts.solswap, &ctx.accounts.admin)?;
+
+        let solswap = &mut ctx.accounts.solswap;
+        solswap.pending_admin = new_admin;
+
+        Ok(())
+    }
+
+    /// Completes the transfer of the pool's admin to the pending admin
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let solswap = &ctx.accounts.solswap;
+        if solswap.pending_admin == Pubkey::default()
+            || *ctx.accounts.pending_admin.key != solswap.pending_admin
+            || !ctx.accounts.pending_admin.is_signer
+        {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+        if solswap.flash_loan_in_progress {
+            return Err(SwapError::FlashLoanInProgress.into());
+        }
+
+        let solswap = &mut ctx.accounts.solswap;
+        solswap.admin = solswap.pending_admin;
+        solswap.pending_admin = Pubkey::default();
+
+        Ok(())
+    }
+
+    /// Creates the program-wide config holding the constraints on new pools,
+    /// editable by the signing config authority, which must be the program's
+    /// upgrade authority so that the config cannot be taken over by someone
+    /// else before the deployer creates it
+    pub fn initialize_global_config(
+        ctx: Context<InitializeGlobalConfig>,
+        constraints: SwapConstraints,
+    ) -> Result<()> {
+        check_upgrade_authority(
+            ctx.program_id,
+            &ctx.accounts.program_data,
+            &ctx.accounts.config_authority,
+        )?;
+        constraints.validate()?;
+        let (_, bump_seed) = Pubkey::find_program_address(&[GLOBAL_CONFIG_SEED], ctx.program_id);
+
+        let global_config = &mut ctx.accounts.global_config;
+        global_config.config_authority = *ctx.accounts.config_authority.key;
+        global_config.bump_seed = bump_seed;
+        global_config.constraints = constraints;
+
+        Ok(())
+    }
+
+    /// Replaces the constraints on new pools
+    pub fn update_global_config(
+        ctx: Context<UpdateGlobalConfig>,
+        constraints: SwapConstraints,
+    ) -> Result<()> {
+        constraints.validate()?;
+
+        let global_config = &mut ctx.accounts.global_config;
+        global_config.constraints = constraints;
+
+        Ok(())
+    }
+
+    /// Hands the global config over to a new config authority
+    pub fn set_config_authority(
+        ctx: Context<UpdateGlobalConfig>,
+        new_config_authority: Pubkey,
+    ) -> Result<()> {
+        let global_config = &mut ctx.accounts.global_config;
+        global_config.config_authority = new_config_authority;
+
+        Ok(())
+    }
+
+    /// Creates a price feed owned by this program, to be used by oracle
+    /// pegged pools in place of an external oracle
+    pub fn initialize_price_feed(
+        ctx: Context<InitializePriceFeed>,
+        price: u64,
+        confidence: u64,
+    ) -> Result<()> {
+        let price_feed = &mut ctx.accounts.price_feed;
+        price_feed.authority = *ctx.accounts.authority.key;
+        price_feed.price = price;
+        price_feed.confidence = confidence;
+        price_feed.last_update_timestamp = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Publishes a new price to a program-owned price feed
+    pub fn update_price_feed(
+        ctx: Context<UpdatePriceFeed>,
+        price: u64,
+        confidence: u64,
+    ) -> Result<()> {
+        let price_feed = &mut ctx.accounts.price_feed;
+        price_feed.price = price;
+        price_feed.confidence = confidence;
+        price_feed.last_update_timestamp = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+}
+
+
+#[derive(Accounts)]
+
+pub struct Initialize<'info> {
+    //CHECK : Safe
+    pub authority: AccountInfo<'info>,
+    #[account(signer, zero)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    #[account(mut)]
+    pub pool_mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub token_a: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub fee_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+
+    //CHECK : Safe
+    pub token_program: AccountInfo<'info>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+    #[account(seeds = [GLOBAL_CONFIG_SEED], bump = global_config.bump_seed)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+}
+
+#[derive(Accounts)]
+
+pub struct Swap<'info> {
+    /// CHECK: Safe
+    pub authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub user_transfer_authority: AccountInfo<'info>,
+    /// CHECK: Safe
+    #[account(mut)]
+    pub source_info: AccountInfo<'info>,
+    /// CHECK: Safe
+    #[account(mut)]
+    pub destination_info: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub swap_source: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub swap_destination: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub pool_mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub pool_fee_account: Account<'info, TokenAccount>,
+    /// CHECK: Safe
+    pub token_program: AccountInfo<'info>,
+    #[account(mut)]
+    pub host_fee_account: Option<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct QuoteSwap<'info> {
+    /// CHECK: Safe
+    pub authority: AccountInfo<'info>,
+    pub solswap: Box<Account<'info, Solswap>>,
+    pub swap_source: Account<'info, TokenAccount>,
+    pub swap_destination: Account<'info, TokenAccount>,
+    pub pool_mint: Account<'info, Mint>,
+    pub pool_fee_account: Account<'info, TokenAccount>,
+    /// CHECK: Safe
+    pub token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DepositAllTokenTypes<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    pub authority: AccountInfo<'info>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub user_transfer_authority_info: AccountInfo<'info>,
+    /// CHECK: Safe
+    #[account(mut)]
+    pub source_a_info: AccountInfo<'info>,
+    /// CHECK: Safe
+    #[account(mut)]
+    pub source_b_info: AccountInfo<'info>,
+    #[account(mut)]
+    pub token_a: Account<'info, TokenAccount>,
+    #[account(mut)]
     pub token_b: Account<'info, TokenAccount>,
     #[account(mut)]
     pub pool_mint: Account<'info, Mint>,
@@ -494,12 +1397,35 @@ pub struct DepositAllTokenTypes<'info> {
     pub destination: AccountInfo<'info>,
     /// CHECK: Safe
     pub token_program: AccountInfo<'info>,
+}
 
-    
+#[derive(Accounts)]
+pub struct DepositSingleTokenType<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    pub authority: AccountInfo<'info>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub user_transfer_authority_info: AccountInfo<'info>,
+    #[account(mut)]
+    pub source: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_a: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub pool_mint: Account<'info, Mint>,
+    /// CHECK: Safe
+    #[account(mut)]
+    pub destination: AccountInfo<'info>,
+    /// CHECK: Safe
+    pub token_program: AccountInfo<'info>,
 }
 
-#[derive(Account)]
+#[derive(Accounts)]
 pub struct WithdrawAllTokenTypes<'info> {
+    #[account(mut)]
     pub solswap: Box<Account<'info, Solswap>>,
     /// CHECK: Safe
     pub authority: AccountInfo<'info>,
@@ -522,23 +1448,292 @@ pub struct WithdrawAllTokenTypes<'info> {
     #[account(mut)]
     pub dest_token_b_info: AccountInfo<'info>,
     /// CHECK: Safe
+    #[account(mut)]
+    pub pool_fee_account: AccountInfo<'info>,
+    /// CHECK: Safe
+    pub token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawSingleTokenType<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    pub authority: AccountInfo<'info>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub user_transfer_authority_info: AccountInfo<'info>,
+    /// CHECK: Safe
+    #[account(mut)]
+    pub source_info: AccountInfo<'info>,
+    #[account(mut)]
+    pub token_a: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub pool_mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    /// CHECK: Safe
+    #[account(mut)]
+    pub pool_fee_account: AccountInfo<'info>,
+    /// CHECK: Safe
+    pub token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetConstantPrice<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+    #[account(address = solswap.token_a_account)]
+    pub token_a: Account<'info, TokenAccount>,
+    #[account(address = solswap.token_b_account)]
+    pub token_b: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct RampAmp<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeObservations<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    #[account(zero)]
+    pub observations: AccountLoader<'info, PoolObservations>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Observe<'info> {
+    pub solswap: Box<Account<'info, Solswap>>,
+    #[account(address = solswap.observations)]
+    pub observations: AccountLoader<'info, PoolObservations>,
+    #[account(address = solswap.token_a_account)]
+    pub token_a: Account<'info, TokenAccount>,
+    #[account(address = solswap.token_b_account)]
+    pub token_b: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct FlashLoan<'info> {
+    /// CHECK: Safe
+    pub authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    #[account(mut, address = solswap.token_a_account)]
+    pub token_a: Account<'info, TokenAccount>,
+    #[account(mut, address = solswap.token_b_account)]
+    pub token_b: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    /// CHECK: Safe
+    pub receiver_program: AccountInfo<'info>,
+    /// CHECK: Safe
+    #[account(address = solswap.token_program_id)]
     pub token_program: AccountInfo<'info>,
 }
 
+#[derive(Accounts)]
+pub struct SetFlashLoanFee<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetFees<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+    #[account(seeds = [GLOBAL_CONFIG_SEED], bump = global_config.bump_seed)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct SetPause<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAdmin<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(mut)]
+    pub solswap: Box<Account<'info, Solswap>>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub pending_admin: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeGlobalConfig<'info> {
+    #[account(
+        init,
+        payer = config_authority,
+        space = GlobalConfig::LEN,
+        seeds = [GLOBAL_CONFIG_SEED],
+        bump
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+    /// CHECK: Safe
+    #[account(mut, signer)]
+    pub config_authority: AccountInfo<'info>,
+    /// CHECK: Safe
+    pub program_data: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateGlobalConfig<'info> {
+    #[account(
+        mut,
+        seeds = [GLOBAL_CONFIG_SEED],
+        bump = global_config.bump_seed,
+        has_one = config_authority
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub config_authority: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePriceFeed<'info> {
+    #[account(zero)]
+    pub price_feed: Account<'info, PriceFeed>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub authority: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePriceFeed<'info> {
+    #[account(mut, has_one = authority)]
+    pub price_feed: Account<'info, PriceFeed>,
+    /// CHECK: Safe
+    #[account(signer)]
+    pub authority: AccountInfo<'info>,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub struct CurveInput {
     pub curve_type: u8,
     pub curve_parameters: u64,
+    // Shift of the curve parameters over time, only used by curves whose
+    // parameters change, such as the liquidity bootstrapping curve or the
+    // amplification coefficient ramp of the stable curve
+    pub curve_schedule: CurveSchedule,
+    // Price range, only used by the concentrated liquidity curve
+    pub price_range: PriceRange,
+    // Price feed and its requirements, only used by the oracle pegged curve
+    pub oracle: OracleConfig,
+    // Limits on price updates, only used by the constant price curve
+    pub price_update: PriceUpdateLimits,
 }
-#[account]
 
-pub struct Solswap {
-    pub initializer_key: Pubkey,
-    pub initializer_deposit_token_account: Pubkey,
-    pub initializer_receive_token_account: Pubkey,
-    pub initializer_amount: u64,
-    pub taker_amount: u64,
+impl CurveInput {
+    pub const LEN: usize = 1 + 8 + 24 + 16 + 56 + 16;
+}
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct PriceUpdateLimits {
+    // Maximum change of the price per update, in basis points
+    pub max_change_bps: u64,
+    // Minimum number of slots between two updates
+    pub min_slot_interval: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct OracleConfig {
+    // Address of the price feed account the curve is centered on
+    pub price_feed: Pubkey,
+    // Spread taken from the output of every swap, in basis points
+    pub spread: u64,
+    // Maximum age of the price, in seconds
+    pub max_staleness: i64,
+    // Maximum confidence interval relative to the price, in basis points
+    pub max_confidence: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct PriceRange {
+    // Lowest price of token B in token A, scaled by `PRICE_DENOMINATOR`
+    pub price_lower: u64,
+    // Highest price of token B in token A, scaled by `PRICE_DENOMINATOR`
+    pub price_upper: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct CurveSchedule {
+    // Curve parameters reached at the end of the schedule
+    pub end_curve_parameters: u64,
+    // Unix timestamp at which the curve parameters start to shift
+    pub start_timestamp: i64,
+    // Unix timestamp at which the curve parameters reach their end value
+    pub end_timestamp: i64,
+}
+
+impl CurveSchedule {
+    /// Curve parameters in force at the given unix timestamp, linearly
+    /// interpolated from `start_curve_parameters` to the end of the schedule
+    pub fn curve_parameters_at(&self, start_curve_parameters: u64, timestamp: i64) -> Option<u64> {
+        if timestamp <= self.start_timestamp {
+            return Some(start_curve_parameters);
+        }
+        if timestamp >= self.end_timestamp {
+            return Some(self.end_curve_parameters);
+        }
+        let elapsed = u128::try_from(timestamp.checked_sub(self.start_timestamp)?).ok()?;
+        let duration =
+            u128::try_from(self.end_timestamp.checked_sub(self.start_timestamp)?).ok()?;
+        let start = u128::from(start_curve_parameters);
+        let end = u128::from(self.end_curve_parameters);
+        let curve_parameters = if end >= start {
+            start.checked_add(
+                end.checked_sub(start)?
+                    .checked_mul(elapsed)?
+                    .checked_div(duration)?,
+            )?
+        } else {
+            start.checked_sub(
+                start
+                    .checked_sub(end)?
+                    .checked_mul(elapsed)?
+                    .checked_div(duration)?,
+            )?
+        };
+        u64::try_from(curve_parameters).ok()
+    }
+}
+#[account]
+#[derive(Default)]
+pub struct Solswap {
     // If swap pool initialized, with data written to it
     pub is_initialized: bool,
     // Bump seed used to generate the program address / authority
@@ -551,16 +1746,260 @@ pub struct Solswap {
     pub token_b_account: Pubkey,
     // Address of pool token mint
     pub pool_mint: Pubkey,
+    // Address of pool fee account
+    pub pool_fee_account: Pubkey,
     // Address of token A mint
     pub token_a_mint: Pubkey,
     // Address of token B mint
     pub token_b_mint: Pubkey,
     // Curve associated with swap
     pub curve: CurveInput,
+    // Fees associated with swap
+    pub fees: CurveFees,
+    // Slot of the last update of the constant price
+    pub last_price_update_slot: u64,
+    // Authority allowed to perform privileged operations on the pool
+    pub admin: Pubkey,
+    // Admin proposed by the current admin, until they accept
+    pub pending_admin: Pubkey,
+    // Number of slots new fees are queued for before being charged
+    pub fee_timelock_slots: u64,
+    // Fees queued by the admin, charged from `pending_fees_slot`
+    pub pending_fees: CurveFees,
+    // Slot from which the pending fees are charged, zero if none are queued
+    pub pending_fees_slot: u64,
+    // If swaps on the pool are halted
+    pub swaps_paused: bool,
+    // If deposits into the pool are halted
+    pub deposits_paused: bool,
+    // If withdrawals from the pool are halted
+    pub withdrawals_paused: bool,
+    // Sum over time of the price of token A in token B, scaled by
+    // `CUMULATIVE_PRICE_SCALE`, wrapping on overflow
+    pub cumulative_price_a: u128,
+    // Sum over time of the price of token B in token A, scaled by
+    // `CUMULATIVE_PRICE_SCALE`, wrapping on overflow
+    pub cumulative_price_b: u128,
+    // Unix timestamp of the last update of the cumulative prices
+    pub cumulative_price_timestamp: i64,
+    // Address of the observations account, if the pool has one
+    pub observations: Pubkey,
+    // Flash loan fee numerator, the fee staying in the pool
+    pub flash_loan_fee_numerator: u64,
+    // Flash loan fee denominator
+    pub flash_loan_fee_denominator: u64,
+    // If a flash loan is being executed, during which the pool cannot be
+    // entered again
+    pub flash_loan_in_progress: bool,
+}
+
+impl Solswap {
+    pub const LEN: usize = 8
+        + 1
+        + 1
+        + 32 * 7
+        + CurveInput::LEN
+        + CurveFees::LEN
+        + 8
+        + 32
+        + 32
+        + 8
+        + CurveFees::LEN
+        + 8
+        + 3
+        + 16
+        + 16
+        + 8
+        + 32
+        + 8
+        + 8
+        + 1;
+
+    /// Fees charged at the given slot, which are the pending fees once their
+    /// timelock has passed
+    pub fn active_fees(&self, slot: u64) -> &CurveFees {
+        if self.pending_fees_slot != 0 && slot >= self.pending_fees_slot {
+            &self.pending_fees
+        } else {
+            &self.fees
+        }
+    }
+}
+
+
+#[account]
+pub struct GlobalConfig {
+    // Authority allowed to edit the constraints
+    pub config_authority: Pubkey,
+    // Bump seed of the global config address
+    pub bump_seed: u8,
+    // Constraints every pool must satisfy
+    pub constraints: SwapConstraints,
+}
+
+impl GlobalConfig {
+    pub const LEN: usize = 8
+        + 32
+        + 1
+        + 32
+        + 4
+        + MAX_VALID_CURVE_TYPES
+        + 2 * CurveFees::LEN
+        + 4
+        + 32 * MAX_VALID_TOKEN_PROGRAMS;
+}
+
+#[account(zero_copy)]
+pub struct PoolObservations {
+    // Address of the swap pool
+    pub pool: Pubkey,
+    // Index of the latest observation
+    pub head: u64,
+    // Number of recorded observations, up to `OBSERVATIONS_LEN`
+    pub count: u64,
+    // Ring buffer of observations
+    pub observations: [Observation; OBSERVATIONS_LEN],
+}
+
+impl PoolObservations {
+    /// Records an observation, overwriting the oldest one once the buffer is
+    /// full.  Observations at the timestamp of the latest one are dropped.
+    pub fn record(&mut self, observation: Observation) {
+        if self.count > 0 {
+            if self.observations[self.head as usize].timestamp >= observation.timestamp {
+                return;
+            }
+            self.head = (self.head + 1) % OBSERVATIONS_LEN as u64;
+        }
+        self.observations[self.head as usize] = observation;
+        self.count = std::cmp::min(self.count + 1, OBSERVATIONS_LEN as u64);
+    }
+
+    /// Latest observation at or before `timestamp`, and the observation
+    /// following it if any, or `None` if `timestamp` precedes all observations
+    pub fn surrounding(&self, timestamp: i64) -> Option<(&Observation, Option<&Observation>)> {
+        let len = OBSERVATIONS_LEN as u64;
+        let mut after = None;
+        // walk back from the latest observation
+        for age in 0..self.count {
+            let index = (self.head + len - age) % len;
+            let observation = &self.observations[index as usize];
+            if observation.timestamp <= timestamp {
+                return Some((observation, after));
+            }
+            after = Some(observation);
+        }
+        None
+    }
+}
+
+#[zero_copy]
+pub struct Observation {
+    // Cumulative price of token A in token B
+    pub cumulative_price_a: u128,
+    // Cumulative price of token B in token A
+    pub cumulative_price_b: u128,
+    // Unix timestamp of the observation
+    pub timestamp: i64,
+    // Amount of token A in the pool
+    pub swap_token_a_amount: u64,
+    // Amount of token B in the pool
+    pub swap_token_b_amount: u64,
+    // Keeps the size a multiple of the alignment of the cumulative prices
+    pub padding: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PoolToken {
+    TokenA,
+    TokenB,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct ObservedPrices {
+    // Cumulative price of token A in token B
+    pub cumulative_price_a: u128,
+    // Cumulative price of token B in token A
+    pub cumulative_price_b: u128,
+}
+
+#[account]
+pub struct PriceFeed {
+    // Authority allowed to publish prices
+    pub authority: Pubkey,
+    // Price of token B in token A, scaled by `ORACLE_PRICE_DENOMINATOR`
+    pub price: u64,
+    // Confidence interval around the price, with the same scale
+    pub confidence: u64,
+    // Unix timestamp of the last published price
+    pub last_update_timestamp: i64,
+}
+
+#[event]
+pub struct PoolInitialized {
+    // Address of the swap pool
+    pub pool: Pubkey,
+    // Address of token A mint
+    pub token_a_mint: Pubkey,
+    // Address of token B mint
+    pub token_b_mint: Pubkey,
+    // Address of pool token mint
+    pub pool_mint: Pubkey,
+    // Curve type of the pool
+    pub curve_type: u8,
+    // Initial amount of token A in the pool
+    pub token_a_amount: u64,
+    // Initial amount of token B in the pool
+    pub token_b_amount: u64,
+    // Amount of pool tokens minted to the initial depositor
+    pub pool_token_amount: u64,
+}
 
+#[event]
+pub struct SwapEvent {
+    // Address of the swap pool
+    pub pool: Pubkey,
+    // Direction of the trade
+    pub trade_direction: TradeDirection,
+    // Amount of source token swapped, including fees
+    pub source_amount_swapped: u64,
+    // Amount of destination token swapped
+    pub destination_amount_swapped: u64,
+    // Amount of source token going to pool holders
+    pub trade_fee: u64,
+    // Amount of source token going to the owner
+    pub owner_fee: u64,
+    // Amount of token A in the pool after the trade
+    pub swap_token_a_amount: u64,
+    // Amount of token B in the pool after the trade
+    pub swap_token_b_amount: u64,
+}
 
+#[event]
+pub struct DepositEvent {
+    // Address of the swap pool
+    pub pool: Pubkey,
+    // Amount of token A deposited
+    pub token_a_amount: u64,
+    // Amount of token B deposited
+    pub token_b_amount: u64,
+    // Amount of pool tokens minted
+    pub pool_token_amount: u64,
 }
 
+#[event]
+pub struct WithdrawEvent {
+    // Address of the swap pool
+    pub pool: Pubkey,
+    // Amount of token A withdrawn
+    pub token_a_amount: u64,
+    // Amount of token B withdrawn
+    pub token_b_amount: u64,
+    // Amount of pool tokens burned
+    pub pool_token_amount: u64,
+    // Amount of pool tokens sent to the owner as withdraw fee
+    pub withdraw_fee: u64,
+}
 
 #[error_code]
 pub enum SwapError {
@@ -659,54 +2098,229 @@ pub enum SwapError {
     // The operation cannot be performed on the given curve
     #[msg("The operation cannot be performed on the given curve")]
     UnsupportedCurveOperation,
+    // The price feed account of the pool was not provided
+    #[msg("The price feed account of the pool was not provided")]
+    IncorrectPriceFeed,
+    // The oracle price has not been updated recently enough
+    #[msg("The oracle price has not been updated recently enough")]
+    StaleOraclePrice,
+
+    // 30.
+    // The confidence interval of the oracle price is too wide
+    #[msg("The confidence interval of the oracle price is too wide")]
+    LowConfidenceOraclePrice,
+    // The new constant price moves too far from the current price
+    #[msg("The new constant price moves too far from the current price")]
+    ExcessivePriceChange,
+    // The constant price was updated too recently
+    #[msg("The constant price was updated too recently")]
+    PriceUpdateTooFrequent,
+    // An amplification coefficient ramp was started too recently
+    #[msg("An amplification coefficient ramp was started too recently")]
+    RampLocked,
+    // The amplification coefficient ramp is too short
+    #[msg("The amplification coefficient ramp is too short")]
+    InvalidRampDuration,
+
+    // 35.
+    // The target amplification coefficient is too far from the current one
+    #[msg("The target amplification coefficient is too far from the current one")]
+    ExcessiveAmpChange,
+    // The signer is not the admin of the pool
+    #[msg("The signer is not the admin of the pool")]
+    InvalidAdmin,
+    // The operation is paused on this pool
+    #[msg("The operation is paused on this pool")]
+    PoolPaused,
+    // The global config constraints are invalid
+    #[msg("The global config constraints are invalid")]
+    InvalidConstraints,
+    // The signer is not the upgrade authority of the program
+    #[msg("The signer is not the upgrade authority of the program")]
+    InvalidUpgradeAuthority,
+
+    // 40.
+    // The requested time is older than the recorded observations
+    #[msg("The requested time is older than the recorded observations")]
+    ObservationTooOld,
+    // Too many timestamps were requested at once
+    #[msg("Too many timestamps were requested at once")]
+    TooManyObservationQueries,
+    // The observations account of the pool was not provided
+    #[msg("The observations account of the pool was not provided")]
+    IncorrectObservations,
+    // A flash loan is in progress on this pool
+    #[msg("A flash loan is in progress on this pool")]
+    FlashLoanInProgress,
+    // The flash loan receiver program is invalid
+    #[msg("The flash loan receiver program is invalid")]
+    InvalidFlashLoanReceiver,
+
+    // 45.
+    // The flash loan was not repaid with its fee
+    #[msg("The flash loan was not repaid with its fee")]
+    FlashLoanNotRepaid,
 }
 
-pub struct SwapConstraints<'a> {
-    /// Owner of the program
-    pub owner_key: &'a str,
-    /// Valid curve types
-    pub valid_curve_types: &'a [CurveType],
-    
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct SwapConstraints {
+    // Owner of the program, who must own the fee account of every pool, no
+    // constraint if left to the default key
+    pub owner_key: Pubkey,
+    // Valid curve types, no constraint if empty
+    pub valid_curve_types: Vec<u8>,
+    // Lowest fees a pool can charge
+    pub min_fees: CurveFees,
+    // Highest fees a pool can charge, a zero denominator meaning no bound
+    pub max_fees: CurveFees,
+    // Valid token programs, no constraint if empty
+    pub valid_token_program_ids: Vec<Pubkey>,
 }
 
-pub const SWAP_CONSTRAINTS: Option<SwapConstraints> = {
-    #[cfg(feature = "production")]
-    {
-        Some(SwapConstraints {
-            owner_key: OWNER_KEY,
-            valid_curve_types: VALID_CURVE_TYPES,
-        })
-    }
-    #[cfg(not(feature = "production"))]
-    {
-        None
+impl SwapConstraints {
+    /// Checks that the lists of constraints fit in the global config account
+    pub fn validate(&self) -> Result<()> {
+        if self.valid_curve_types.len() > MAX_VALID_CURVE_TYPES
+            || self.valid_token_program_ids.len() > MAX_VALID_TOKEN_PROGRAMS
+        {
+            return Err(SwapError::InvalidConstraints.into());
+        }
+        self.min_fees.validate()?;
+        self.max_fees.validate()?;
+        Ok(())
     }
-};
 
-impl<'a> SwapConstraints<'a> {
     /// Checks that the provided curve is valid for the given constraints
     pub fn validate_curve(&self, swap_curve: &SwapCurve) -> Result<()> {
-        if self
-            .valid_curve_types
-            .iter()
-            .any(|x| *x == swap_curve.curve_type)
+        if self.valid_curve_types.is_empty()
+            || self
+                .valid_curve_types
+                .contains(&(swap_curve.curve_type as u8))
         {
             Ok(())
         } else {
             Err(SwapError::UnsupportedCurveType.into())
         }
     }
+
+    /// Checks that every fee fraction lies between the minimum and maximum
+    /// fees
+    pub fn validate_fees(&self, fees: &CurveFees) -> Result<()> {
+        let fractions = [
+            (
+                fees.trade_fee_numerator,
+                fees.trade_fee_denominator,
+                self.min_fees.trade_fee_numerator,
+                self.min_fees.trade_fee_denominator,
+                self.max_fees.trade_fee_numerator,
+                self.max_fees.trade_fee_denominator,
+            ),
+            (
+                fees.owner_trade_fee_numerator,
+                fees.owner_trade_fee_denominator,
+                self.min_fees.owner_trade_fee_numerator,
+                self.min_fees.owner_trade_fee_denominator,
+                self.max_fees.owner_trade_fee_numerator,
+                self.max_fees.owner_trade_fee_denominator,
+            ),
+            (
+                fees.owner_withdraw_fee_numerator,
+                fees.owner_withdraw_fee_denominator,
+                self.min_fees.owner_withdraw_fee_numerator,
+                self.min_fees.owner_withdraw_fee_denominator,
+                self.max_fees.owner_withdraw_fee_numerator,
+                self.max_fees.owner_withdraw_fee_denominator,
+            ),
+            (
+                fees.host_fee_numerator,
+                fees.host_fee_denominator,
+                self.min_fees.host_fee_numerator,
+                self.min_fees.host_fee_denominator,
+                self.max_fees.host_fee_numerator,
+                self.max_fees.host_fee_denominator,
+            ),
+        ];
+        if fractions.iter().all(
+            |&(numerator, denominator, min_numerator, min_denominator, max_numerator, max_denominator)| {
+                fee_fraction_in_bounds(
+                    numerator,
+                    denominator,
+                    min_numerator,
+                    min_denominator,
+                    max_numerator,
+                    max_denominator,
+                )
+            },
+        ) {
+            Ok(())
+        } else {
+            Err(SwapError::InvalidFee.into())
+        }
+    }
+
+    /// Checks that the provided token program is valid for the given
+    /// constraints
+    pub fn validate_token_program(&self, token_program_id: &Pubkey) -> Result<()> {
+        if self.valid_token_program_ids.is_empty()
+            || self.valid_token_program_ids.contains(token_program_id)
+        {
+            Ok(())
+        } else {
+            Err(SwapError::IncorrectTokenProgramId.into())
+        }
+    }
+}
+
+/// Checks that the fee fraction `numerator / denominator` is at least
+/// `min_numerator / min_denominator` and at most
+/// `max_numerator / max_denominator`.  A zero denominator stands for a zero
+/// fee, except for the maximum where it stands for no bound.
+fn fee_fraction_in_bounds(
+    numerator: u64,
+    denominator: u64,
+    min_numerator: u64,
+    min_denominator: u64,
+    max_numerator: u64,
+    max_denominator: u64,
+) -> bool {
+    let numerator = u128::from(numerator);
+    let denominator = u128::from(denominator);
+    let (numerator, denominator) = if denominator == 0 {
+        (0, 1)
+    } else {
+        (numerator, denominator)
+    };
+    let above_min = min_denominator == 0
+        || numerator * u128::from(min_denominator) >= u128::from(min_numerator) * denominator;
+    let below_max = max_denominator == 0
+        || numerator * u128::from(max_denominator) <= u128::from(max_numerator) * denominator;
+    above_min && below_max
 }
 
 // Context
 
 impl<'info> Initialize<'info> {
-    fn into_mint_to_context(&self) ->CpiContext<'_, '_, '_, 'info, MintTo<'info>> {
+    fn into_mint_to_context(&self) -> CpiContext<'_, '_, '_, 'info, MintTo<'info>> {
         let cpi_accounts = MintTo {
             mint: self.pool_mint.to_account_info().clone(),
             to: self.destination.to_account_info().clone(),
             authority: self.authority.clone(),
-        }
+        };
+
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
+}
+
+impl<'info> FlashLoan<'info> {
+    fn into_transfer_to_destination_context(
+        &self,
+        vault: AccountInfo<'info>,
+    ) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let cpi_accounts = Transfer {
+            from: vault,
+            to: self.destination.to_account_info().clone(),
+            authority: self.authority.clone(),
+        };
 
         CpiContext::new(self.token_program.clone(), cpi_accounts)
     }
@@ -733,6 +2347,28 @@ impl<'info> Swap<'info> {
         CpiContext::new(self.token_program.clone(), cpi_accounts)
     }
 
+    fn into_mint_to_pool_fee_context(&self) -> CpiContext<'_, '_, '_, 'info, MintTo<'info>> {
+        let cpi_accounts = MintTo {
+            mint: self.pool_mint.to_account_info().clone(),
+            to: self.pool_fee_account.to_account_info().clone(),
+            authority: self.authority.clone(),
+        };
+
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
+
+    fn into_mint_to_host_fee_context(
+        &self,
+        host_fee_account: AccountInfo<'info>,
+    ) -> CpiContext<'_, '_, '_, 'info, MintTo<'info>> {
+        let cpi_accounts = MintTo {
+            mint: self.pool_mint.to_account_info().clone(),
+            to: host_fee_account,
+            authority: self.authority.clone(),
+        };
+
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
 }
 
 impl<'info> DepositAllTokenTypes<'info> {
@@ -764,11 +2400,49 @@ impl<'info> DepositAllTokenTypes<'info> {
     }
 }
 
+impl<'info> DepositSingleTokenType<'info> {
+    fn into_transfer_to_token_a_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let cpi_accounts = Transfer {
+            from: self.source.to_account_info().clone(),
+            to: self.token_a.to_account_info().clone(),
+            authority: self.user_transfer_authority_info.clone(),
+        };
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
+
+    fn into_transfer_to_token_b_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let cpi_accounts = Transfer {
+            from: self.source.to_account_info().clone(),
+            to: self.token_b.to_account_info().clone(),
+            authority: self.user_transfer_authority_info.clone(),
+        };
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
+
+    fn into_mint_to_context(&self) -> CpiContext<'_, '_, '_, 'info, MintTo<'info>> {
+        let cpi_accounts = MintTo {
+            mint: self.pool_mint.to_account_info().clone(),
+            to: self.destination.clone(),
+            authority: self.authority.clone(),
+        };
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
+}
+
 impl<'info> WithdrawAllTokenTypes<'info> {
+    fn into_transfer_to_pool_fee_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let cpi_accounts = Transfer {
+            from: self.source_info.clone(),
+            to: self.pool_fee_account.clone(),
+            authority: self.user_transfer_authority_info.clone(),
+        };
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
+
     fn into_burn_context(&self) -> CpiContext<'_, '_, '_, 'info, Burn<'info>> {
         let cpi_accounts = Burn{
             mint: self.pool_mint.to_account_info().clone(),
-            to: self.source_info.clone(),
+            from: self.source_info.clone(),
             authority: self.user_transfer_authority_info.clone(),
         };
         CpiContext::new(self.token_program.clone(), cpi_accounts)
@@ -793,6 +2467,44 @@ impl<'info> WithdrawAllTokenTypes<'info> {
     }
 }
 
+impl<'info> WithdrawSingleTokenType<'info> {
+    fn into_transfer_to_pool_fee_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let cpi_accounts = Transfer {
+            from: self.source_info.clone(),
+            to: self.pool_fee_account.clone(),
+            authority: self.user_transfer_authority_info.clone(),
+        };
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
+
+    fn into_burn_context(&self) -> CpiContext<'_, '_, '_, 'info, Burn<'info>> {
+        let cpi_accounts = Burn {
+            mint: self.pool_mint.to_account_info().clone(),
+            from: self.source_info.clone(),
+            authority: self.user_transfer_authority_info.clone(),
+        };
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
+
+    fn into_transfer_from_token_a_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let cpi_accounts = Transfer {
+            from: self.token_a.to_account_info().clone(),
+            to: self.destination.to_account_info().clone(),
+            authority: self.authority.clone(),
+        };
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
+
+    fn into_transfer_from_token_b_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let cpi_accounts = Transfer {
+            from: self.token_b.to_account_info().clone(),
+            to: self.destination.to_account_info().clone(),
+            authority: self.authority.clone(),
+        };
+        CpiContext::new(self.token_program.clone(), cpi_accounts)
+    }
+}
+
 
 // Utils
 
@@ -808,7 +2520,7 @@ fn check_accounts(
     token_program_info: &AccountInfo,
     user_token_a_info: Option<&AccountInfo>,
     user_token_b_info: Option<&AccountInfo>,
-    // pool_fee_account_info: Option<&AccountInfo>,
+    pool_fee_account_info: Option<&AccountInfo>,
 ) -> Result<()> {
     if solswap_account_info.owner != program_id {
         return Err(ProgramError::IncorrectProgramId.into());
@@ -838,7 +2550,427 @@ fn check_accounts(
             return Err(SwapError::InvalidInput.into());
         }
     }
-    
+    if let Some(pool_fee_account_info) = pool_fee_account_info {
+        if *pool_fee_account_info.key != solswap.pool_fee_account {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks the amounts of trading tokens moved by a deposit or withdrawal of
+/// both tokens.  A side can only be empty if the pool holds none of that
+/// token, such as a concentrated liquidity pool outside of its range, and at
+/// least one side held by the pool must move.
+fn check_trading_token_amounts(
+    token_a_amount: u64,
+    token_b_amount: u64,
+    swap_token_a_amount: u64,
+    swap_token_b_amount: u64,
+) -> Result<()> {
+    if (token_a_amount == 0 && swap_token_a_amount != 0)
+        || (token_b_amount == 0 && swap_token_b_amount != 0)
+        || !((token_a_amount != 0 && swap_token_a_amount != 0)
+            || (token_b_amount != 0 && swap_token_b_amount != 0))
+    {
+        return Err(SwapError::ZeroTradingTokens.into());
+    }
+    Ok(())
+}
+
+/// Adds the prices in force since the last update to the cumulative prices,
+/// weighted by the elapsed time, and records them in the pool's observations.
+/// Must be called with the reserves before any tokens move, so that the
+/// prices can only be moved by trades made in earlier blocks.
+fn update_cumulative_prices(
+    solswap: &mut Solswap,
+    curve: &SwapCurve,
+    swap_token_a_amount: u64,
+    swap_token_b_amount: u64,
+    remaining_accounts: &[AccountInfo],
+) -> Result<()> {
+    let timestamp = Clock::get()?.unix_timestamp;
+    if timestamp <= solswap.cumulative_price_timestamp {
+        return Ok(());
+    }
+    let (cumulative_price_a, cumulative_price_b) = cumulative_prices_at(
+        solswap,
+        curve,
+        swap_token_a_amount,
+        swap_token_b_amount,
+        timestamp,
+    )?;
+    solswap.cumulative_price_a = cumulative_price_a;
+    solswap.cumulative_price_b = cumulative_price_b;
+    solswap.cumulative_price_timestamp = timestamp;
+
+    record_observation(
+        solswap,
+        remaining_accounts,
+        swap_token_a_amount,
+        swap_token_b_amount,
+    )
+}
+
+/// Cumulative prices of token A and token B at the given timestamp, assuming
+/// the pool held the given reserves since the last update
+fn cumulative_prices_at(
+    solswap: &Solswap,
+    curve: &SwapCurve,
+    swap_token_a_amount: u64,
+    swap_token_b_amount: u64,
+    timestamp: i64,
+) -> Result<(u128, u128)> {
+    let elapsed = timestamp
+        .checked_sub(solswap.cumulative_price_timestamp)
+        .ok_or(SwapError::CalculationFailure)?;
+    if elapsed <= 0 {
+        return Ok((solswap.cumulative_price_a, solswap.cumulative_price_b));
+    }
+    let elapsed = u128::try_from(elapsed).unwrap();
+
+    // an empty side has no price, in which case the time is skipped
+    if let Some(price_b) = curve.calculator.spot_price(
+        u128::try_from(swap_token_a_amount).unwrap(),
+        u128::try_from(swap_token_b_amount).unwrap(),
+    ) {
+        let scale =
+            PreciseNumber::new(CUMULATIVE_PRICE_SCALE).ok_or(SwapError::CalculationFailure)?;
+        let scaled_price_b = price_b
+            .checked_mul(&scale)
+            .and_then(|price| price.floor())
+            .and_then(|price| price.to_imprecise())
+            .ok_or(SwapError::CalculationFailure)?;
+        let scaled_price_a = if scaled_price_b == 0 {
+            0
+        } else {
+            scale
+                .checked_div(&price_b)
+                .and_then(|price| price.floor())
+                .and_then(|price| price.to_imprecise())
+                .ok_or(SwapError::CalculationFailure)?
+        };
+        Ok((
+            solswap
+                .cumulative_price_a
+                .wrapping_add(scaled_price_a.wrapping_mul(elapsed)),
+            solswap
+                .cumulative_price_b
+                .wrapping_add(scaled_price_b.wrapping_mul(elapsed)),
+        ))
+    } else {
+        Ok((solswap.cumulative_price_a, solswap.cumulative_price_b))
+    }
+}
+
+/// Records the pool's cumulative prices and reserves in its observations
+/// account, which must be passed among the remaining accounts if the pool has
+/// one, so that the cumulative prices between two observations always grow
+/// linearly.  At most one observation is recorded per timestamp.
+fn record_observation(
+    solswap: &Solswap,
+    remaining_accounts: &[AccountInfo],
+    swap_token_a_amount: u64,
+    swap_token_b_amount: u64,
+) -> Result<()> {
+    if solswap.observations == Pubkey::default() {
+        return Ok(());
+    }
+    let observations_info = remaining_accounts
+        .iter()
+        .find(|account| *account.key == solswap.observations)
+        .ok_or(SwapError::IncorrectObservations)?;
+    if *observations_info.owner != crate::ID {
+        return Err(SwapError::IncorrectObservations.into());
+    }
+    let mut data = observations_info.try_borrow_mut_data()?;
+    if data.get(..8) != Some(&PoolObservations::DISCRIMINATOR[..]) {
+        return Err(SwapError::IncorrectObservations.into());
+    }
+    let observations: &mut PoolObservations = bytemuck::from_bytes_mut(
+        data.get_mut(8..8 + std::mem::size_of::<PoolObservations>())
+            .ok_or(SwapError::IncorrectObservations)?,
+    );
+    observations.record(Observation {
+        cumulative_price_a: solswap.cumulative_price_a,
+        cumulative_price_b: solswap.cumulative_price_b,
+        timestamp: solswap.cumulative_price_timestamp,
+        swap_token_a_amount,
+        swap_token_b_amount,
+        padding: 0,
+    });
+
+    Ok(())
+}
+
+/// Interpolates the cumulative prices at `timestamp` between two observations
+fn interpolate_observations(
+    before: &Observation,
+    after: &Observation,
+    timestamp: i64,
+) -> Option<ObservedPrices> {
+    let elapsed = u128::try_from(timestamp.checked_sub(before.timestamp)?).ok()?;
+    let duration = u128::try_from(after.timestamp.checked_sub(before.timestamp)?).ok()?;
+    if elapsed == 0 || duration == 0 {
+        return Some(ObservedPrices {
+            cumulative_price_a: before.cumulative_price_a,
+            cumulative_price_b: before.cumulative_price_b,
+        });
+    }
+    // the cumulative prices grow linearly between two observations, since the
+    // price only changes with the reserves
+    let interpolate = |start: u128, end: u128| {
+        Some(start.wrapping_add(
+            U256::from(end.wrapping_sub(start))
+                .checked_mul(U256::from(elapsed))?
+                .checked_div(U256::from(duration))?
+                .as_u128(),
+        ))
+    };
+    Some(ObservedPrices {
+        cumulative_price_a: interpolate(before.cumulative_price_a, after.cumulative_price_a)?,
+        cumulative_price_b: interpolate(before.cumulative_price_b, after.cumulative_price_b)?,
+    })
+}
+
+/// Time-weighted average price between two snapshots of a cumulative price,
+/// scaled by `CUMULATIVE_PRICE_SCALE`.  The difference of the cumulative
+/// prices is computed with wrapping arithmetic, so the result is correct as
+/// long as the cumulative price wrapped at most once between the snapshots.
+pub fn twap(
+    start_cumulative_price: u128,
+    start_timestamp: i64,
+    end_cumulative_price: u128,
+    end_timestamp: i64,
+) -> Option<u128> {
+    let elapsed = end_timestamp.checked_sub(start_timestamp)?;
+    if elapsed <= 0 {
+        return None;
+    }
+    end_cumulative_price
+        .wrapping_sub(start_cumulative_price)
+        .checked_div(u128::try_from(elapsed).ok()?)
+}
+
+/// Checks that the program's upgrade authority, read from the program data
+/// account of the program, signed the instruction
+fn check_upgrade_authority(
+    program_id: &Pubkey,
+    program_data_info: &AccountInfo,
+    authority_info: &AccountInfo,
+) -> Result<()> {
+    let (program_data_address, _) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    if *program_data_info.key != program_data_address
+        || *program_data_info.owner != bpf_loader_upgradeable::id()
+    {
+        return Err(SwapError::InvalidUpgradeAuthority.into());
+    }
+    // program data starts with the u32 `ProgramData` state tag (3) and the
+    // u64 deployment slot, followed by the optional upgrade authority
+    let data = program_data_info.try_borrow_data()?;
+    let upgrade_authority = match (data.get(0..4), data.get(12..45)) {
+        (Some([3, 0, 0, 0]), Some([1, key @ ..])) => {
+            Pubkey::try_from(key).map_err(|_| SwapError::InvalidUpgradeAuthority)?
+        }
+        _ => return Err(SwapError::InvalidUpgradeAuthority.into()),
+    };
+    if *authority_info.key != upgrade_authority || !authority_info.is_signer {
+        return Err(SwapError::InvalidUpgradeAuthority.into());
+    }
+    Ok(())
+}
+
+/// Checks that the pool's admin signed the instruction, and that no flash
+/// loan is in progress, which would let its receiver change the pool under
+/// the loan
+fn check_admin(solswap: &Solswap, admin_info: &AccountInfo) -> Result<()> {
+    if *admin_info.key != solswap.admin || !admin_info.is_signer {
+        return Err(SwapError::InvalidAdmin.into());
+    }
+    if solswap.flash_loan_in_progress {
+        return Err(SwapError::FlashLoanInProgress.into());
+    }
+    Ok(())
+}
+
+/// Checks the accounts shared by `swap` and `swap_exact_out` and returns the
+/// direction of the trade
+fn check_swap_accounts(program_id: &Pubkey, accounts: &Swap) -> Result<TradeDirection> {
+    let trade_direction = check_pool_swap_accounts(
+        &accounts.solswap,
+        program_id,
+        &accounts.authority,
+        &accounts.swap_source.to_account_info(),
+        &accounts.swap_destination.to_account_info(),
+        &accounts.pool_mint.to_account_info(),
+        &accounts.pool_fee_account.to_account_info(),
+        &accounts.token_program,
+    )?;
+
+    if *accounts.swap_source.to_account_info().key == *accounts.source_info.key {
+        return Err(SwapError::InvalidInput.into());
+    }
+
+    if *accounts.swap_destination.to_account_info().key == *accounts.destination_info.key {
+        return Err(SwapError::InvalidInput.into());
+    }
+
+    Ok(trade_direction)
+}
+
+/// Checks the pool accounts of a swap or quote and returns the direction of
+/// the trade
+#[allow(clippy::too_many_arguments)]
+fn check_pool_swap_accounts(
+    solswap: &Account<Solswap>,
+    program_id: &Pubkey,
+    authority_info: &AccountInfo,
+    swap_source_info: &AccountInfo,
+    swap_destination_info: &AccountInfo,
+    pool_mint_info: &AccountInfo,
+    pool_fee_account_info: &AccountInfo,
+    token_program_info: &AccountInfo,
+) -> Result<TradeDirection> {
+    if solswap.to_account_info().owner != program_id {
+        return Err(ProgramError::IncorrectProgramId.into());
+    }
+
+    if solswap.swaps_paused {
+        return Err(SwapError::PoolPaused.into());
+    }
+
+    if solswap.flash_loan_in_progress {
+        return Err(SwapError::FlashLoanInProgress.into());
+    }
+
+    if *authority_info.key != authority_id(program_id, solswap.to_account_info().key, solswap.bump_seed)? {
+        return Err(SwapError::InvalidProgramAddress.into());
+    }
+
+    if !(*swap_source_info.key == solswap.token_a_account
+        || *swap_source_info.key == solswap.token_b_account) {
+            return Err(SwapError::IncorrectSwapAccount.into());
+    }
+
+    if !(*swap_destination_info.key == solswap.token_a_account
+        || *swap_destination_info.key == solswap.token_b_account) {
+            return Err(SwapError::IncorrectSwapAccount.into());
+    }
+
+    if *swap_source_info.key == *swap_destination_info.key {
+        return Err(SwapError::InvalidInput.into());
+    }
+
+    if *pool_mint_info.key != solswap.pool_mint {
+        return Err(SwapError::IncorrectPoolMint.into());
+    }
+
+    if *pool_fee_account_info.key != solswap.pool_fee_account {
+        return Err(SwapError::IncorrectFeeAccount.into());
+    }
+
+    if *token_program_info.key != solswap.token_program_id {
+        return Err(SwapError::IncorrectTokenProgramId.into());
+    }
+
+    if *swap_source_info.key == solswap.token_a_account {
+        Ok(TradeDirection::AtoB)
+    } else {
+        Ok(TradeDirection::BtoA)
+    }
+}
+
+/// Moves the tokens of a calculated swap and mints the owner and host fees
+fn settle_swap<'info>(
+    ctx: &Context<'_, '_, '_, 'info, Swap<'info>>,
+    curve: &SwapCurve,
+    result: &SwapResult,
+    trade_direction: TradeDirection,
+) -> Result<()> {
+    let accounts = &ctx.accounts;
+    let solswap = &accounts.solswap;
+
+    let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+        TradeDirection::AtoB => (
+            result.new_swap_source_amount,
+            result.new_swap_destination_amount,
+        ),
+        TradeDirection::BtoA => (
+            result.new_swap_destination_amount,
+            result.new_swap_source_amount,
+        ),
+    };
+
+    let seeds = &[&solswap.to_account_info().key.to_bytes(), &[solswap.bump_seed][..]];
+
+    token::transfer(
+        ctx.accounts
+            .into_transfer_to_swap_source_context()
+            .with_signer(&[&seeds[..]]),
+        u64::try_from(result.source_amount_swapped).unwrap(),
+    )?;
+
+    token::transfer(
+        ctx.accounts
+            .into_transfer_to_destination_context()
+            .with_signer(&[&seeds[..]]),
+        u64::try_from(result.destination_amount_swapped).unwrap(),
+    )?;
+
+    emit!(SwapEvent {
+        pool: *solswap.to_account_info().key,
+        trade_direction,
+        source_amount_swapped: u64::try_from(result.source_amount_swapped).unwrap(),
+        destination_amount_swapped: u64::try_from(result.destination_amount_swapped).unwrap(),
+        trade_fee: u64::try_from(result.trade_fee).unwrap(),
+        owner_fee: u64::try_from(result.owner_fee).unwrap(),
+        swap_token_a_amount: u64::try_from(swap_token_a_amount).unwrap(),
+        swap_token_b_amount: u64::try_from(swap_token_b_amount).unwrap(),
+    });
+
+    // mint pool tokens equivalent to the owner fee
+    let mut pool_token_amount = curve
+        .calculator
+        .withdraw_single_token_type_exact_out(
+            result.owner_fee,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            u128::try_from(accounts.pool_mint.supply).unwrap(),
+            trade_direction,
+        )
+        .ok_or(SwapError::FeeCalculationFailure)?;
+
+    if pool_token_amount > 0 {
+        // the optional host fee account receives a share of the owner fee
+        if let Some(host_fee_account) = &accounts.host_fee_account {
+            if host_fee_account.mint != solswap.pool_mint {
+                return Err(SwapError::IncorrectPoolMint.into());
+            }
+            let host_fee = solswap
+                .active_fees(Clock::get()?.slot)
+                .host_fee(pool_token_amount)
+                .ok_or(SwapError::FeeCalculationFailure)?;
+            if host_fee > 0 {
+                pool_token_amount = pool_token_amount
+                    .checked_sub(host_fee)
+                    .ok_or(SwapError::FeeCalculationFailure)?;
+                token::mint_to(
+                    ctx.accounts
+                        .into_mint_to_host_fee_context(host_fee_account.to_account_info())
+                        .with_signer(&[&seeds[..]]),
+                    u64::try_from(host_fee).unwrap(),
+                )?;
+            }
+        }
+        token::mint_to(
+            ctx.accounts
+                .into_mint_to_pool_fee_context()
+                .with_signer(&[&seeds[..]]),
+            u64::try_from(pool_token_amount).unwrap(),
+        )?;
+    }
+
     Ok(())
 }
 
@@ -848,24 +2980,126 @@ pub fn authority_id(program_id: &Pubkey, my_info: &Pubkey, bump_seed: u8) -> Res
         .or(Err(SwapError::InvalidProgramAddress.into()))
 }
 
-/// Build Curve object and Fee object
-pub fn build_curve(curve_input: &CurveInput) -> Result<SwapCurve> {
-    let curve_type = CurveType::try_from(curve_input.curve_type).unwrap();
+/// Builds the curve of a pool at the current time, reading the oracle price
+/// from the remaining accounts if the curve needs one
+pub fn load_curve(curve_input: &CurveInput, remaining_accounts: &[AccountInfo]) -> Result<SwapCurve> {
+    let timestamp = Clock::get()?.unix_timestamp;
+    let oracle_price = load_oracle_price(curve_input, remaining_accounts, timestamp)?;
+    build_curve(curve_input, timestamp, oracle_price)
+}
+
+/// Reads the price of the pool's price feed, which must be passed among the
+/// remaining accounts, rejecting stale and low-confidence prices.  Curves
+/// that are not pegged to an oracle don't need a price, so zero is returned.
+pub fn load_oracle_price(
+    curve_input: &CurveInput,
+    remaining_accounts: &[AccountInfo],
+    timestamp: i64,
+) -> Result<u64> {
+    let curve_type = CurveType::try_from(curve_input.curve_type)
+        .map_err(|_| SwapError::UnsupportedCurveType)?;
+    if curve_type != CurveType::OraclePegged {
+        return Ok(0);
+    }
+    let oracle = &curve_input.oracle;
+    let price_feed_info = remaining_accounts
+        .iter()
+        .find(|account| *account.key == oracle.price_feed)
+        .ok_or(SwapError::IncorrectPriceFeed)?;
+    if *price_feed_info.owner != crate::ID {
+        return Err(SwapError::IncorrectPriceFeed.into());
+    }
+    let price_feed = PriceFeed::try_deserialize(&mut &price_feed_info.try_borrow_data()?[..])?;
+
+    // a price from the future is no more trustworthy than a stale one
+    let age = timestamp
+        .checked_sub(price_feed.last_update_timestamp)
+        .ok_or(SwapError::CalculationFailure)?;
+    if age < 0 || age > oracle.max_staleness {
+        return Err(SwapError::StaleOraclePrice.into());
+    }
+    // the confidence interval must be at most `max_confidence` basis points
+    // of the price, which also rejects a zero price
+    let confidence = u128::from(price_feed.confidence)
+        .checked_mul(u128::from(BPS_DENOMINATOR))
+        .ok_or(SwapError::CalculationFailure)?;
+    let max_confidence = u128::from(oracle.max_confidence)
+        .checked_mul(u128::from(price_feed.price))
+        .ok_or(SwapError::CalculationFailure)?;
+    if price_feed.price == 0 || confidence > max_confidence {
+        return Err(SwapError::LowConfidenceOraclePrice.into());
+    }
+    Ok(price_feed.price)
+}
+
+/// Build Curve object and Fee object, pricing time-dependent curves at the
+/// given unix timestamp and oracle-pegged curves at the given oracle price
+pub fn build_curve(curve_input: &CurveInput, timestamp: i64, oracle_price: u64) -> Result<SwapCurve> {
+    let curve_type = CurveType::try_from(curve_input.curve_type)
+        .map_err(|_| SwapError::UnsupportedCurveType)?;
     let culculator: Box<dyn CurveCalculator> = match curve_type {
         CurveType::ConstantProduct => Box::new(ConstantProductCurve {}),
         CurveType::ConstantPrice => Box::new(ConstantPriceCurve {
             token_b_price: curve_input.curve_parameters,
         }),
         CurveType::Stable => Box::new(StableCurve {
-            amp: curve_input.curve_parameters,
+            amp: curve_input
+                .curve_schedule
+                .curve_parameters_at(curve_input.curve_parameters, timestamp)
+                .ok_or(SwapError::CalculationFailure)?,
         }),
         CurveType::Offset => Box::new(OffsetCurve {
             token_b_offset: curve_input.curve_parameters,
         }),
+        CurveType::Weighted => Box::new(WeightedCurve {
+            token_a_weight: curve_input.curve_parameters,
+        }),
+        CurveType::LiquidityBootstrapping => Box::new(LiquidityBootstrappingCurve {
+            start_token_a_weight: curve_input.curve_parameters,
+            end_token_a_weight: curve_input.curve_schedule.end_curve_parameters,
+            start_timestamp: curve_input.curve_schedule.start_timestamp,
+            end_timestamp: curve_input.curve_schedule.end_timestamp,
+            current_timestamp: timestamp,
+        }),
+        CurveType::ConcentratedLiquidity => Box::new(ConcentratedLiquidityCurve {
+            price_lower: curve_input.price_range.price_lower,
+            price_upper: curve_input.price_range.price_upper,
+        }),
+        CurveType::OraclePegged => Box::new(OraclePeggedCurve {
+            amp: curve_input.curve_parameters,
+            spread: curve_input.oracle.spread,
+            oracle_price,
+        }),
     };
     let curve = SwapCurve {
-        curve_type: curve_type,
+        curve_type,
         calculator: culculator,
     };
     Ok(curve)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trading_token_amounts() {
+        // both sides of a pool holding both tokens must move
+        assert!(check_trading_token_amounts(1, 1, 10, 10).is_ok());
+        assert!(check_trading_token_amounts(0, 1, 10, 10).is_err());
+        assert!(check_trading_token_amounts(1, 0, 10, 10).is_err());
+        // an empty side of the pool can stay empty
+        assert!(check_trading_token_amounts(1, 0, 10, 0).is_ok());
+        assert!(check_trading_token_amounts(0, 1, 0, 10).is_ok());
+        // but something held by the pool has to move
+        assert!(check_trading_token_amounts(0, 0, 10, 0).is_err());
+        assert!(check_trading_token_amounts(0, 1, 10, 0).is_err());
+        assert!(check_trading_token_amounts(0, 0, 0, 0).is_err());
+        assert!(check_trading_token_amounts(1, 1, 0, 0).is_err());
+    }
+
+    #[test]
+    fn solswap_len() {
+        assert_eq!(Solswap::LEN, 8 + Solswap::default().try_to_vec().unwrap().len());
+    }
+}